@@ -1,9 +1,3 @@
-CREATE TABLE todos
-(
-    id        SERIAL PRIMARY KEY,
-    text      TEXT    NOT NULL,
-    completed BOOLEAN NOT NULL DEFAULT false
-);
 CREATE TABLE users
 (
     id            SERIAL PRIMARY KEY,
@@ -0,0 +1,30 @@
+CREATE OR REPLACE FUNCTION notify_todo_event() RETURNS trigger AS
+$$
+DECLARE
+    kind TEXT;
+    todo todos;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        kind := 'created';
+        todo := NEW;
+    ELSIF TG_OP = 'UPDATE' THEN
+        IF NEW.completed AND NOT OLD.completed THEN
+            kind := 'completed';
+        ELSE
+            kind := 'updated';
+        END IF;
+        todo := NEW;
+    ELSE
+        kind := 'deleted';
+        todo := OLD;
+    END IF;
+    PERFORM pg_notify('todo_events', json_build_object('kind', kind, 'todo', row_to_json(todo))::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_notify_event
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todos
+    FOR EACH ROW
+EXECUTE FUNCTION notify_todo_event();
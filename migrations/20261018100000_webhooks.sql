@@ -0,0 +1,23 @@
+CREATE TABLE webhooks
+(
+    id      SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    url     TEXT    NOT NULL,
+    secret  TEXT    NOT NULL,
+    events  TEXT[]  NOT NULL DEFAULT '{}'
+);
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
+
+CREATE TABLE webhook_deliveries
+(
+    id          SERIAL PRIMARY KEY,
+    webhook_id  INTEGER     NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event       TEXT        NOT NULL,
+    payload     TEXT        NOT NULL,
+    attempt     INTEGER     NOT NULL,
+    status_code INTEGER,
+    error       TEXT,
+    succeeded   BOOLEAN     NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id);
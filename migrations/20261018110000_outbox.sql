@@ -0,0 +1,11 @@
+DROP TRIGGER todos_notify_event ON todos;
+DROP FUNCTION notify_todo_event();
+
+CREATE TABLE outbox
+(
+    id           BIGSERIAL PRIMARY KEY,
+    payload      TEXT        NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ
+);
+CREATE INDEX outbox_undelivered_idx ON outbox (id) WHERE delivered_at IS NULL;
@@ -0,0 +1,8 @@
+ALTER TABLE todos ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- deletions leave no row behind, so the list remembers when it last lost one
+CREATE TABLE todo_list
+(
+    id              BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    last_deleted_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,2 @@
+-- why a row was set aside undelivered
+ALTER TABLE outbox ADD COLUMN error TEXT;
@@ -0,0 +1,10 @@
+-- deliveries the outbox relay queued for the webhook worker, kept until they succeed or give up
+CREATE TABLE webhook_queue
+(
+    id              BIGSERIAL PRIMARY KEY,
+    webhook_id      INTEGER     NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    payload         TEXT        NOT NULL,
+    attempts        INTEGER     NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX webhook_queue_next_attempt_at_idx ON webhook_queue (next_attempt_at);
@@ -0,0 +1,49 @@
+-- SQLite counterpart of the Postgres schema; timestamps are RFC 3339 text written by the application
+CREATE TABLE todos
+(
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    text       TEXT     NOT NULL,
+    completed  BOOLEAN  NOT NULL DEFAULT FALSE,
+    updated_at DATETIME NOT NULL
+);
+
+-- deletions leave no row behind, so the list remembers when it last lost one
+CREATE TABLE todo_list
+(
+    id              BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    last_deleted_at DATETIME NOT NULL
+);
+
+CREATE TABLE users
+(
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    username      TEXT    NOT NULL,
+    email         TEXT    NOT NULL UNIQUE,
+    password_hash TEXT    NOT NULL,
+    disabled      BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE webhooks
+(
+    id      INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    url     TEXT    NOT NULL,
+    secret  TEXT    NOT NULL,
+    -- JSON array of event kinds
+    events  TEXT    NOT NULL DEFAULT '[]'
+);
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
+
+CREATE TABLE webhook_deliveries
+(
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id  INTEGER  NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event       TEXT     NOT NULL,
+    payload     TEXT     NOT NULL,
+    attempt     INTEGER  NOT NULL,
+    status_code INTEGER,
+    error       TEXT,
+    succeeded   BOOLEAN  NOT NULL,
+    created_at  DATETIME NOT NULL
+);
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id);
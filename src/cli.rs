@@ -0,0 +1,522 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use sqlx::PgPool;
+use validator::Validate;
+
+use crate::config::{Backend, Config};
+use crate::events::EventBus;
+use crate::handlers::auth::{hash_password, JwtKeys};
+use crate::migrations::{self, State};
+use crate::repositories::event_sourced::EventSourcedRepository;
+use crate::repositories::file::FileRepository;
+use crate::repositories::postgres::{self, PostgresRepository, MIGRATOR};
+use crate::repositories::sqlite::{self, SqliteRepository};
+use crate::repositories::todos::{CreateTodo, TodoRepository, UpdateTodo};
+use crate::repositories::users::{CreateUser, UpdateUser, User, UserRepository};
+use crate::repositories::Transactional;
+
+/// Todo API server and the commands to administer it.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Serve the API; what runs without a command
+    Serve,
+    #[command(flatten)]
+    Admin(AdminCommand),
+}
+
+/// Commands that administer the service instead of serving it.
+#[derive(Debug, Subcommand)]
+pub(crate) enum AdminCommand {
+    /// Inspect and change the database schema
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Move todos in and out as JSON, and rebuild their projection
+    #[command(subcommand)]
+    Todos(TodosCommand),
+    /// Issue API tokens
+    #[command(subcommand)]
+    Token(TokenCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum MigrateCommand {
+    /// Apply the pending migrations
+    Up,
+    /// Revert the latest migration, or every migration after the target
+    Down {
+        /// Version to go back to
+        #[arg(long)]
+        target: Option<i64>,
+    },
+    /// List the migrations and whether they are applied
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum UserCommand {
+    /// Create a user; the password is prompted for, or read from standard input
+    Create {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        email: String,
+    },
+    /// List every user
+    List,
+    /// Stop issuing tokens to a user and refuse the ones they hold
+    Disable { email: String },
+    /// Issue tokens to a disabled user again
+    Enable { email: String },
+    /// Set a new password; it is prompted for, or read from standard input
+    ResetPassword { email: String },
+    /// Delete a user along with their webhooks
+    Delete { email: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum TodosCommand {
+    /// Write every todo as a JSON array
+    Export {
+        /// File to write instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Create todos from a JSON array like the one `export` writes; ids are assigned anew
+    Import {
+        /// File to read instead of standard input
+        #[arg(long, short)]
+        input: Option<PathBuf>,
+    },
+    /// Fold the todos of the events backend from their whole event log again, replacing the
+    /// snapshot
+    Rebuild,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum TokenCommand {
+    /// Print a bearer token for a user, e.g. a service account
+    Issue {
+        /// Email of the user
+        #[arg(long)]
+        user: String,
+        /// Days until the token expires, at most ten years
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(1..=3650))]
+        ttl_days: i64,
+    },
+}
+
+pub(crate) async fn run(command: AdminCommand, config: &Config) -> anyhow::Result<()> {
+    let backend = config
+        .database
+        .backend()
+        .context("unsupported database url")?;
+    match backend {
+        Backend::Postgres => {
+            let pool = postgres::connect(&config.database).await?;
+            let result = match command {
+                AdminCommand::Migrate(command) => migrate(command, &pool).await,
+                command => manage(command, &PostgresRepository::new(pool.clone()), config).await,
+            };
+            pool.close().await;
+            result
+        }
+        Backend::Sqlite => {
+            // opening the database applies its migrations already
+            let pool = sqlite::connect(&config.database).await?;
+            let repository = SqliteRepository::new(pool.clone(), EventBus::default());
+            let result = match command {
+                AdminCommand::Migrate(MigrateCommand::Up) => Ok(()),
+                AdminCommand::Migrate(_) => Err(anyhow::anyhow!(
+                    "SQLite databases are migrated whenever they are opened, \
+                     only `migrate up` is supported"
+                )),
+                command => manage(command, &repository, config).await,
+            };
+            pool.close().await;
+            result
+        }
+        Backend::File => {
+            // the directory is locked, so this only works while the server is stopped
+            let repository = FileRepository::open(&config.database, EventBus::default()).await?;
+            match command {
+                AdminCommand::Migrate(MigrateCommand::Up) => Ok(()),
+                AdminCommand::Migrate(_) => Err(anyhow::anyhow!(
+                    "the file backend has no schema, only `migrate up` is supported"
+                )),
+                command => manage(command, &repository, config).await,
+            }
+        }
+        Backend::Events => {
+            // like the file backend, this only works while the server is stopped
+            match command {
+                AdminCommand::Migrate(MigrateCommand::Up) => Ok(()),
+                AdminCommand::Migrate(_) => Err(anyhow::anyhow!(
+                    "the events backend has no schema, only `migrate up` is supported"
+                )),
+                AdminCommand::Todos(TodosCommand::Export { output }) => {
+                    let todos =
+                        EventSourcedRepository::open(&config.database, EventBus::default()).await?;
+                    export(output, &todos).await
+                }
+                AdminCommand::Todos(TodosCommand::Import { .. }) => Err(anyhow::anyhow!(
+                    "the events backend cannot import todos in one transaction, \
+                     create them through the API instead"
+                )),
+                AdminCommand::Todos(TodosCommand::Rebuild) => {
+                    let todos =
+                        EventSourcedRepository::rebuild(&config.database, EventBus::default())
+                            .await?;
+                    let events = todos.read_projection().position();
+                    eprintln!("rebuilt the projection from {} events", events);
+                    Ok(())
+                }
+                command => {
+                    let repository =
+                        FileRepository::open(&config.database, EventBus::default()).await?;
+                    manage(command, &repository, config).await
+                }
+            }
+        }
+        Backend::Memory => anyhow::bail!(
+            "the memory backend keeps data in the server process only, there is nothing to administer"
+        ),
+    }
+}
+
+/// Runs the commands that only go through the repositories.
+async fn manage<R: Transactional>(
+    command: AdminCommand,
+    repository: &R,
+    config: &Config,
+) -> anyhow::Result<()> {
+    match command {
+        AdminCommand::Migrate(_) => unreachable!("migrations depend on the backend"),
+        AdminCommand::User(command) => user(command, repository).await,
+        AdminCommand::Todos(command) => todos(command, repository).await,
+        AdminCommand::Token(command) => token(command, repository, config).await,
+    }
+}
+
+async fn migrate(command: MigrateCommand, pool: &PgPool) -> anyhow::Result<()> {
+    match command {
+        MigrateCommand::Up => migrations::run(pool, &MIGRATOR).await?,
+        MigrateCommand::Down { target } => {
+            let reverted = migrations::undo(pool, &MIGRATOR, target).await?;
+            if reverted.is_empty() {
+                println!("nothing to revert");
+            }
+            for version in reverted {
+                println!("reverted {}", version);
+            }
+        }
+        MigrateCommand::Status => {
+            for (version, description, state) in migrations::status(pool, &MIGRATOR).await? {
+                let state = match state {
+                    State::Applied => "applied",
+                    State::Pending => "pending",
+                    State::Unknown => "unknown",
+                };
+                println!("{:<16}{:<9}{}", version, state, description);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn user<R: UserRepository>(command: UserCommand, repository: &R) -> anyhow::Result<()> {
+    match command {
+        UserCommand::Create { username, email } => {
+            let password_hash = hash_password(&read_password()?)?;
+            let user = repository
+                .create(CreateUser {
+                    username,
+                    email,
+                    password_hash,
+                })
+                .await?;
+            println!("created user {}", user.id);
+        }
+        UserCommand::List => {
+            for user in repository.all().await? {
+                let state = if user.disabled { "disabled" } else { "enabled" };
+                println!(
+                    "{:<8}{:<10}{:<24}{}",
+                    user.id, state, user.username, user.email
+                );
+            }
+        }
+        UserCommand::Disable { email } => {
+            let user = set_disabled(repository, &email, true).await?;
+            println!("disabled user {}", user.id);
+        }
+        UserCommand::Enable { email } => {
+            let user = set_disabled(repository, &email, false).await?;
+            println!("enabled user {}", user.id);
+        }
+        UserCommand::ResetPassword { email } => {
+            let user = repository.find_by_email(&email).await?;
+            let password_hash = hash_password(&read_password()?)?;
+            repository
+                .update(
+                    user.id,
+                    UpdateUser {
+                        password_hash: Some(password_hash),
+                        ..UpdateUser::default()
+                    },
+                )
+                .await?;
+            println!("reset the password of user {}", user.id);
+        }
+        UserCommand::Delete { email } => {
+            let user = repository.find_by_email(&email).await?;
+            repository.delete(user.id).await?;
+            println!("deleted user {}", user.id);
+        }
+    }
+    Ok(())
+}
+
+async fn set_disabled<R: UserRepository>(
+    repository: &R,
+    email: &str,
+    disabled: bool,
+) -> anyhow::Result<User> {
+    let user = repository.find_by_email(email).await?;
+    repository
+        .update(
+            user.id,
+            UpdateUser {
+                disabled: Some(disabled),
+                ..UpdateUser::default()
+            },
+        )
+        .await
+}
+
+/// Prompts without echo on a terminal, and takes the first line of piped input otherwise.
+fn read_password() -> anyhow::Result<String> {
+    let password = if io::stdin().is_terminal() {
+        rpassword::prompt_password("Password: ")?
+    } else {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        anyhow::bail!("the password must not be empty");
+    }
+    Ok(password)
+}
+
+/// A todo as `export` writes it; fields it does not need, like the id, are ignored.
+#[derive(Debug, Deserialize)]
+struct ImportedTodo {
+    text: String,
+    #[serde(default)]
+    completed: bool,
+}
+
+async fn todos<T: Transactional>(command: TodosCommand, repository: &T) -> anyhow::Result<()> {
+    match command {
+        TodosCommand::Export { output } => export(output, repository).await?,
+        TodosCommand::Import { input } => {
+            let input: Box<dyn Read> = match &input {
+                Some(path) => Box::new(
+                    File::open(path)
+                        .with_context(|| format!("failed to open [{}]", path.display()))?,
+                ),
+                None => Box::new(io::stdin().lock()),
+            };
+            let todos: Vec<ImportedTodo> = serde_json::from_reader(BufReader::new(input))
+                .context("expected a JSON array of todos")?;
+            // validate everything up front, rather than fail in the middle of the transaction
+            let todos = todos
+                .into_iter()
+                .enumerate()
+                .map(|(i, todo)| {
+                    let payload = CreateTodo::new(todo.text);
+                    payload
+                        .validate()
+                        .with_context(|| format!("todo {} is invalid", i))?;
+                    Ok((payload, todo.completed))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let count = todos.len();
+            repository
+                .transaction(|repository| {
+                    Box::pin(async move {
+                        for (payload, completed) in todos {
+                            let todo = TodoRepository::create(repository, payload).await?;
+                            if completed {
+                                let payload = UpdateTodo {
+                                    text: None,
+                                    completed: Some(true),
+                                };
+                                TodoRepository::update(repository, todo.id, payload).await?;
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .await?;
+            eprintln!("imported {} todos", count);
+        }
+        TodosCommand::Rebuild => {
+            anyhow::bail!("only the events backend has a projection to rebuild")
+        }
+    }
+    Ok(())
+}
+
+async fn export<T: TodoRepository>(output: Option<PathBuf>, repository: &T) -> anyhow::Result<()> {
+    let mut todos = TodoRepository::all(repository).await?;
+    todos.sort_by_key(|todo| todo.id);
+    let output: Box<dyn Write> = match &output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("failed to create [{}]", path.display()))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut output = BufWriter::new(output);
+    serde_json::to_writer_pretty(&mut output, &todos)?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+async fn token<R: UserRepository>(
+    command: TokenCommand,
+    repository: &R,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let TokenCommand::Issue { user, ttl_days } = command;
+    let keys = JwtKeys::new(config.auth.require_secret()?.as_bytes());
+    let user = repository.find_by_email(&user).await?;
+    if user.disabled {
+        anyhow::bail!("user {} is disabled", user.id);
+    }
+    println!("{}", keys.issue(user.id, chrono::Duration::days(ttl_days))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::repositories::hash_map::HashMapRepository;
+    use crate::repositories::todos::Todo;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn serve_without_command() {
+        assert!(Cli::try_parse_from(["my-todo"]).unwrap().command.is_none());
+        let cli = Cli::try_parse_from(["my-todo", "migrate", "down", "--target", "42"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Admin(AdminCommand::Migrate(
+                MigrateCommand::Down { target: Some(42) }
+            )))
+        ));
+    }
+
+    #[test]
+    fn bound_token_ttl() {
+        let issue = |ttl: &str| {
+            Cli::try_parse_from([
+                "my-todo",
+                "token",
+                "issue",
+                "--user",
+                "user@example.com",
+                "--ttl-days",
+                ttl,
+            ])
+        };
+        assert!(issue("3650").is_ok());
+        assert!(issue("0").is_err());
+        assert!(issue("9223372036854775807").is_err());
+    }
+
+    #[tokio::test]
+    async fn export_and_import_todos() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.json");
+        let source = HashMapRepository::new();
+        for text in ["first", "second"] {
+            TodoRepository::create(&source, CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+        let completed = UpdateTodo {
+            text: None,
+            completed: Some(true),
+        };
+        TodoRepository::update(&source, 2, completed).await.unwrap();
+        let export = TodosCommand::Export {
+            output: Some(path.clone()),
+        };
+        todos(export, &source).await.unwrap();
+
+        let target = HashMapRepository::new();
+        todos(TodosCommand::Import { input: Some(path) }, &target)
+            .await
+            .unwrap();
+        let imported = TodoRepository::all(&target).await.unwrap();
+        let completed = Todo {
+            completed: true,
+            ..Todo::new(2, "second".to_string())
+        };
+        assert_eq!(vec![completed, Todo::new(1, "first".to_string())], imported);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_import_entirely() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.json");
+        std::fs::write(&path, r#"[{"text": "valid"}, {"text": ""}]"#).unwrap();
+        let repository = HashMapRepository::new();
+        let import = TodosCommand::Import { input: Some(path) };
+        assert!(todos(import, &repository).await.is_err());
+        assert!(TodoRepository::all(&repository).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn need_secret_only_to_issue_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.database.url = format!("file://{}", dir.path().display());
+        assert!(config.auth.jwt_secret.is_empty());
+
+        let export = TodosCommand::Export {
+            output: Some(dir.path().join("todos.json")),
+        };
+        run(AdminCommand::Todos(export), &config).await.unwrap();
+
+        let issue = TokenCommand::Issue {
+            user: "user@example.com".to_string(),
+            ttl_days: 1,
+        };
+        let err = run(AdminCommand::Token(issue), &config).await.unwrap_err();
+        assert_eq!("JWT_SECRET must be defined", err.to_string());
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::headers::{Cookie, HeaderMapExt};
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::config::DatabaseConfig;
+use crate::handlers::auth::{bearer_token, JwtKeys};
+
+/// The cookie marking a client that wrote recently, whose reads go to the primary.
+const COOKIE_NAME: &str = "read_primary";
+
+tokio::task_local! {
+    static READ_PRIMARY: bool;
+}
+
+/// Whether the request being served must read from the primary; reads outside of requests,
+/// e.g. of the admin commands, may go to a replica.
+pub(crate) fn reads_from_primary() -> bool {
+    READ_PRIMARY.try_with(|primary| *primary).unwrap_or(false)
+}
+
+/// Runs `future` as a request that must read from the primary.
+#[cfg(test)]
+pub(crate) async fn read_from_primary<F: std::future::Future>(future: F) -> F::Output {
+    READ_PRIMARY.scope(true, future).await
+}
+
+/// For how long a client keeps reading from the primary after a write, if replicas are used.
+#[derive(Clone)]
+pub(crate) struct ReadYourWrites {
+    window: Option<Duration>,
+    keys: Arc<JwtKeys>,
+    /// The users who wrote within the window, so that API clients read their writes without
+    /// keeping cookies; only on this instance, unlike the cookie.
+    writers: Arc<Mutex<Writers>>,
+}
+
+#[derive(Debug)]
+struct Writers {
+    /// Until when each user reads from the primary.
+    until: HashMap<i32, Instant>,
+    pruned: Instant,
+}
+
+impl ReadYourWrites {
+    pub(crate) fn new(config: &DatabaseConfig, keys: Arc<JwtKeys>) -> Self {
+        let window = config.read_your_writes();
+        let writers = Writers {
+            until: HashMap::new(),
+            pruned: Instant::now(),
+        };
+        Self {
+            window: (!config.replica_urls.is_empty() && !window.is_zero()).then_some(window),
+            keys,
+            writers: Arc::new(Mutex::new(writers)),
+        }
+    }
+
+    fn wrote_recently(&self, user_id: i32, now: Instant) -> bool {
+        let writers = self.writers.lock().unwrap();
+        writers
+            .until
+            .get(&user_id)
+            .is_some_and(|until| now < *until)
+    }
+
+    fn wrote(&self, user_id: i32, now: Instant, window: Duration) {
+        let mut writers = self.writers.lock().unwrap();
+        // entries outlive their window by at most another one
+        if now.saturating_duration_since(writers.pruned) >= window {
+            writers.until.retain(|_, until| now < *until);
+            writers.pruned = now;
+        }
+        writers.until.insert(user_id, now + window);
+    }
+}
+
+/// Marks the clients whose writes succeed, by their user and with a cookie lasting the
+/// read-your-writes window, and serves the reads of marked clients from the primary.
+pub(crate) async fn read_your_writes<B>(
+    State(policy): State<ReadYourWrites>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(window) = policy.window else {
+        return next.run(request).await;
+    };
+    let write = !request.method().is_safe();
+    let now = Instant::now();
+    let user_id = bearer_token(request.headers()).and_then(|token| policy.keys.verify(token).ok());
+    let marked = request
+        .headers()
+        .typed_get::<Cookie>()
+        .is_some_and(|cookie| cookie.get(COOKIE_NAME).is_some())
+        || user_id.is_some_and(|user_id| policy.wrote_recently(user_id, now));
+    let mut response = READ_PRIMARY.scope(marked, next.run(request)).await;
+    if write && response.status().is_success() {
+        if let Some(user_id) = user_id {
+            policy.wrote(user_id, now, window);
+        }
+        let cookie = format!(
+            "{}=1; Max-Age={}; Path=/; HttpOnly; SameSite=Lax",
+            COOKIE_NAME,
+            window.as_secs()
+        );
+        let cookie = HeaderValue::from_str(&cookie).expect("the cookie is a valid header value");
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::header::{AUTHORIZATION, COOKIE};
+    use axum::http::Method;
+    use axum::middleware;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+
+    fn app(replicas: Vec<String>) -> Router {
+        let config = DatabaseConfig {
+            replica_urls: replicas,
+            ..DatabaseConfig::default()
+        };
+        Router::new()
+            .route(
+                "/",
+                get(|| async { reads_from_primary().to_string() }).post(|| async {}),
+            )
+            .layer(middleware::from_fn_with_state(
+                ReadYourWrites::new(&config, Arc::new(JwtKeys::new(SECRET))),
+                read_your_writes,
+            ))
+    }
+
+    async fn send(app: &Router, method: Method, cookie: Option<&str>) -> Response {
+        let mut request = Request::builder().method(method).uri("/");
+        if let Some(cookie) = cookie {
+            request = request.header(COOKIE, cookie);
+        }
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn send_as(app: &Router, method: Method, user_id: i32) -> Response {
+        let token = JwtKeys::new(SECRET)
+            .issue(user_id, chrono::Duration::minutes(5))
+            .unwrap();
+        let request = Request::builder()
+            .method(method)
+            .uri("/")
+            .header(AUTHORIZATION, format!("Bearer {}", token));
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_from_primary_after_write() {
+        let app = app(vec!["postgres://replica/todos".to_string()]);
+        let response = send(&app, Method::GET, None).await;
+        assert!(response.headers().get(SET_COOKIE).is_none());
+        assert_eq!("false", text(response).await);
+
+        let response = send(&app, Method::POST, None).await;
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert_eq!(
+            "read_primary=1; Max-Age=5; Path=/; HttpOnly; SameSite=Lax",
+            cookie
+        );
+        let response = send(&app, Method::GET, Some("theme=dark; read_primary=1")).await;
+        assert_eq!("true", text(response).await);
+    }
+
+    #[tokio::test]
+    async fn read_from_primary_after_write_without_cookie() {
+        let app = app(vec!["postgres://replica/todos".to_string()]);
+        assert_eq!("false", text(send_as(&app, Method::GET, 1).await).await);
+
+        let response = send_as(&app, Method::POST, 1).await;
+        assert!(response.status().is_success());
+        // the client drops the cookie, as API clients do
+        assert_eq!("true", text(send_as(&app, Method::GET, 1).await).await);
+        assert_eq!("false", text(send_as(&app, Method::GET, 2).await).await);
+    }
+
+    #[tokio::test]
+    async fn mark_nobody_without_replicas() {
+        let app = app(Vec::new());
+        let response = send(&app, Method::POST, None).await;
+        assert!(response.headers().get(SET_COOKIE).is_none());
+    }
+}
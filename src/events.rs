@@ -0,0 +1,146 @@
+use std::sync::{Arc, Mutex};
+
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+use crate::repositories::todos::Todo;
+
+pub(crate) mod listener;
+
+/// Postgres channel on which the outbox relay publishes changes.
+pub(crate) const TODO_EVENTS_CHANNEL: &str = "todo_events";
+
+const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TodoEventKind {
+    Created,
+    Updated,
+    Completed,
+    Deleted,
+}
+
+impl TodoEventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TodoEventKind::Created => "created",
+            TodoEventKind::Updated => "updated",
+            TodoEventKind::Completed => "completed",
+            TodoEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct TodoEvent {
+    pub(crate) kind: TodoEventKind,
+    pub(crate) todo: Todo,
+}
+
+/// In-process fan-out of todo events to the subscribers of this instance.
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<TodoEvent>,
+    closed: CancellationToken,
+}
+
+impl EventBus {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    pub(crate) fn publish(&self, event: TodoEvent) {
+        // having no subscriber at the moment is not an error
+        let _ = self.sender.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<TodoEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Tells long-lived subscribers such as event streams to finish, e.g. on shutdown.
+    pub(crate) fn close(&self) {
+        self.closed.cancel();
+    }
+
+    pub(crate) fn closed(&self) -> WaitForCancellationFutureOwned {
+        self.closed.clone().cancelled_owned()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Where a repository that publishes its own events sends them: the bus, or the transaction
+/// it works in, which publishes them once committed.
+#[derive(Debug, Clone)]
+pub(crate) enum Publisher {
+    Bus(EventBus),
+    Deferred(Arc<Mutex<Vec<TodoEvent>>>),
+}
+
+impl Publisher {
+    pub(crate) fn deferred() -> Self {
+        Publisher::Deferred(Arc::default())
+    }
+
+    pub(crate) fn publish(&self, event: TodoEvent) {
+        match self {
+            Publisher::Bus(bus) => bus.publish(event),
+            Publisher::Deferred(events) => events.lock().unwrap().push(event),
+        }
+    }
+
+    /// The events held back so far, which are handed over only once.
+    pub(crate) fn take(&self) -> Vec<TodoEvent> {
+        match self {
+            Publisher::Bus(_) => Vec::new(),
+            Publisher::Deferred(events) => std::mem::take(&mut *events.lock().unwrap()),
+        }
+    }
+}
+
+/// Destination `forward` hands the events published on this instance to.
+#[async_trait]
+pub(crate) trait EventSink: Send + Sync {
+    async fn deliver(&self, event: &TodoEvent) -> anyhow::Result<()>;
+}
+
+/// Hands every event published on this instance to `sink` until `shutdown` is cancelled.
+///
+/// This stands in for the outbox relay on backends that publish on the bus directly; events
+/// the sink falls too far behind on are lost.
+pub(crate) async fn forward(
+    mut receiver: broadcast::Receiver<TodoEvent>,
+    sink: Arc<dyn EventSink>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            event = receiver.recv() => event,
+        };
+        match event {
+            Ok(event) => {
+                if let Err(e) = sink.deliver(&event).await {
+                    tracing::error!("failed to forward {:?} event: {}", event.kind, e);
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("fell behind and dropped {} todo events", skipped)
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
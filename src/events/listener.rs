@@ -0,0 +1,33 @@
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use crate::events::{EventBus, TodoEvent, TODO_EVENTS_CHANNEL};
+
+/// Forwards the notifications sent by any instance through `pg_notify` to the local `EventBus`.
+///
+/// `PgListener` reconnects on its own when the connection is lost, so this only returns on
+/// errors it cannot recover from or once `shutdown` is cancelled.
+pub(crate) async fn listen(
+    pool: PgPool,
+    bus: EventBus,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(TODO_EVENTS_CHANNEL).await?;
+    tracing::debug!("listening on channel [{}]", TODO_EVENTS_CHANNEL);
+    loop {
+        let notification = tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            notification = listener.recv() => notification?,
+        };
+        match serde_json::from_str::<TodoEvent>(notification.payload()) {
+            Ok(event) => bus.publish(event),
+            Err(e) => tracing::warn!(
+                "ignored malformed notification [{}]: {}",
+                notification.payload(),
+                e
+            ),
+        }
+    }
+}
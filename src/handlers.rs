@@ -3,9 +3,46 @@ use axum::extract::FromRequest;
 use axum::http::Request;
 use axum::{async_trait, http::StatusCode, BoxError, Json};
 use serde::de::DeserializeOwned;
+use sqlx::error::DatabaseError;
+use sqlx::sqlite::SqliteError;
 use validator::Validate;
 
+pub(crate) mod auth;
+pub(crate) mod events;
+pub(crate) mod health;
+pub(crate) mod metrics;
 pub(crate) mod todos;
+pub(crate) mod webhooks;
+
+/// `SQLITE_BUSY`, the primary result code of a database locked by another connection.
+const SQLITE_BUSY: i32 = 5;
+
+/// The status of a failed repository call: 503 when no database connection was free in time
+/// or SQLite gave up waiting for another writer, so that clients retry later rather than take
+/// it for their mistake, and `otherwise` for anything else.
+pub(crate) fn error_status(e: &anyhow::Error, otherwise: StatusCode) -> StatusCode {
+    let exhausted = e
+        .chain()
+        .any(|cause| matches!(cause.downcast_ref(), Some(sqlx::Error::PoolTimedOut)));
+    if exhausted {
+        tracing::warn!("no database connection was free in time");
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    let busy = e.chain().any(|cause| match cause.downcast_ref() {
+        Some(sqlx::Error::Database(e)) => e
+            .try_downcast_ref::<SqliteError>()
+            .and_then(|e| e.code())
+            .and_then(|code| code.parse::<i32>().ok())
+            // the primary result code of the extended ones, e.g. SQLITE_BUSY_SNAPSHOT
+            .is_some_and(|code| code & 0xff == SQLITE_BUSY),
+        _ => false,
+    });
+    if busy {
+        tracing::warn!("the database stayed locked by another writer");
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    otherwise
+}
 
 #[derive(Debug)]
 pub(crate) struct ValidatedJson<T>(T);
@@ -25,8 +62,12 @@ where
         let Json(value) = Json::<T>::from_request(req, &state)
             .await
             .map_err(|rejection| {
+                let status = match rejection.status() {
+                    StatusCode::PAYLOAD_TOO_LARGE => StatusCode::PAYLOAD_TOO_LARGE,
+                    _ => StatusCode::BAD_REQUEST,
+                };
                 let message = format!("Json parse error: [{}]", rejection);
-                (StatusCode::BAD_REQUEST, message)
+                (status, message)
             })?;
         value.validate().map_err(|rejection| {
             let message = format!("Validation error: [{}]", rejection).replace('\n', ", ");
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::{async_trait, Extension};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::handlers::error_status;
+use crate::repositories::users::UserRepository;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: i32,
+    exp: usize,
+}
+
+/// Secret of the HS256 tokens the API accepts as `Authorization: Bearer <token>`.
+#[derive(Clone)]
+pub(crate) struct JwtKeys {
+    secret: Vec<u8>,
+}
+
+impl JwtKeys {
+    pub(crate) fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    pub(crate) fn issue(&self, user_id: i32, ttl: chrono::Duration) -> anyhow::Result<String> {
+        let claims = Claims {
+            sub: user_id,
+            exp: (chrono::Utc::now() + ttl).timestamp() as usize,
+        };
+        let key = EncodingKey::from_secret(&self.secret);
+        Ok(encode(&Header::default(), &claims, &key)?)
+    }
+
+    pub(crate) fn verify(&self, token: &str) -> anyhow::Result<i32> {
+        let key = DecodingKey::from_secret(&self.secret);
+        let data = decode::<Claims>(token, &key, &Validation::default())?;
+        Ok(data.claims.sub)
+    }
+}
+
+/// The users tokens are issued to, looked up on every authenticated request so that
+/// disabling or deleting a user revokes the tokens it was issued.
+pub(crate) type Users = Arc<dyn UserRepository + Send + Sync>;
+
+/// Id of the user the bearer token was issued to, who must still exist and be enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AuthUser(pub(crate) i32);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(keys) = Extension::<Arc<JwtKeys>>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| (StatusCode::INTERNAL_SERVER_ERROR, rejection.to_string()))?;
+        let token = bearer_token(&parts.headers)
+            .ok_or((StatusCode::UNAUTHORIZED, "Missing bearer token".to_string()))?;
+        let user_id = keys.verify(token).map_err(|rejection| {
+            let message = format!("Invalid token: [{}]", rejection);
+            (StatusCode::UNAUTHORIZED, message)
+        })?;
+        let Extension(users) = Extension::<Users>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| (StatusCode::INTERNAL_SERVER_ERROR, rejection.to_string()))?;
+        let user = users.find_by_id(user_id).await.map_err(|e| {
+            let status = error_status(&e, StatusCode::UNAUTHORIZED);
+            (status, "Unknown user".to_string())
+        })?;
+        if user.disabled {
+            return Err((StatusCode::UNAUTHORIZED, "User is disabled".to_string()));
+        }
+        Ok(AuthUser(user_id))
+    }
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// PHC string of `password` hashed with Argon2id under a random salt.
+pub(crate) fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("failed to hash the password: {}", e))?;
+    Ok(hash.to_string())
+}
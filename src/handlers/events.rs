@@ -0,0 +1,28 @@
+use std::convert::Infallible;
+
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::events::EventBus;
+
+pub(crate) async fn todo_events(
+    State(events): State<EventBus>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(events.subscribe()).filter_map(|received| match received {
+        Ok(event) => Event::default()
+            .event(event.kind.as_str())
+            .json_data(&event)
+            .map_err(|e| tracing::warn!("failed to serialize todo event: {}", e))
+            .ok()
+            .map(Ok),
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            tracing::warn!("subscriber lagged behind, skipped {} todo events", skipped);
+            None
+        }
+    });
+    let stream = futures_util::StreamExt::take_until(stream, events.closed());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
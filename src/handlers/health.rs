@@ -0,0 +1,22 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::json;
+
+use crate::health::{Readiness, Status};
+
+/// Liveness: answers as long as the process can serve requests at all.
+pub(crate) async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, Json(json!({ "status": "up" })))
+}
+
+/// Readiness: reports every dependency and fails with 503 while any of them is down.
+pub(crate) async fn readyz(State(readiness): State<Readiness>) -> impl IntoResponse {
+    let report = readiness.report().await;
+    let status = match report.status {
+        Status::Up => StatusCode::OK,
+        Status::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report))
+}
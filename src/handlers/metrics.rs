@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+
+use crate::metrics::Metrics;
+use crate::repositories::todos::TodoRepository;
+
+pub(crate) async fn render_metrics<T: TodoRepository>(
+    State(repository): State<Arc<T>>,
+    Extension(metrics): Extension<Metrics>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // business gauges are refreshed on scrape rather than on every write
+    match repository.stats().await {
+        Ok(stats) => metrics.set_todo_stats(stats),
+        Err(e) => tracing::warn!("failed to count todos: {}", e),
+    }
+    let body = metrics
+        .encode()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
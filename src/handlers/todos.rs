@@ -1,12 +1,15 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
-use axum::Json;
+use axum::headers::{CacheControl, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, TypedHeader};
+use chrono::{DateTime, Utc};
 
-use crate::handlers::ValidatedJson;
-use crate::repositories::todos::{CreateTodo, TodoRepository, UpdateTodo};
+use crate::handlers::{error_status, ValidatedJson};
+use crate::repositories::todos::{CreateTodo, NoHistory, TodoRepository, UpdateTodo};
 
 pub(crate) async fn create_todo<T: TodoRepository>(
     State(repository): State<Arc<T>>,
@@ -15,7 +18,7 @@ pub(crate) async fn create_todo<T: TodoRepository>(
     let todo = repository
         .create(payload)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+        .map_err(|e| error_status(&e, StatusCode::NOT_FOUND))?;
 
     Ok((StatusCode::CREATED, Json(todo)))
 }
@@ -28,35 +31,116 @@ pub(crate) async fn update_todo<T: TodoRepository>(
     let todo = repository
         .update(id, payload)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+        .map_err(|e| error_status(&e, StatusCode::NOT_FOUND))?;
     Ok((StatusCode::CREATED, Json(todo)))
 }
 
 pub(crate) async fn all_todo<T: TodoRepository>(
     State(repository): State<Arc<T>>,
-) -> anyhow::Result<(StatusCode, impl IntoResponse), StatusCode> {
-    let todo = repository.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todo)))
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    // read before the list, so that a change in between makes the next request fetch it again
+    let last_modified = repository
+        .last_modified()
+        .await
+        .map_err(|e| error_status(&e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    if not_modified(&headers, last_modified) {
+        return Ok(cached(StatusCode::NOT_MODIFIED, last_modified, ()));
+    }
+    let todo = repository
+        .all()
+        .await
+        .map_err(|e| error_status(&e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(cached(StatusCode::OK, last_modified, Json(todo)))
 }
 
 pub(crate) async fn find_todo<T: TodoRepository>(
     Path(id): Path<i32>,
     State(repository): State<Arc<T>>,
-) -> Result<(StatusCode, impl IntoResponse), StatusCode> {
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
     let todo = repository
         .find(id)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
-    Ok((StatusCode::OK, Json(todo)))
+        .map_err(|e| error_status(&e, StatusCode::NOT_FOUND))?;
+    let last_modified = Some(todo.updated_at);
+    if not_modified(&headers, last_modified) {
+        return Ok(cached(StatusCode::NOT_MODIFIED, last_modified, ()));
+    }
+    Ok(cached(StatusCode::OK, last_modified, Json(todo)))
+}
+
+/// The events of a todo, which stay listed once it is deleted; 501 on stores that keep none.
+pub(crate) async fn todo_history<T: TodoRepository>(
+    Path(id): Path<i32>,
+    State(repository): State<Arc<T>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let events = repository.history(id).await.map_err(|e| {
+        if e.is::<NoHistory>() {
+            StatusCode::NOT_IMPLEMENTED
+        } else {
+            error_status(&e, StatusCode::NOT_FOUND)
+        }
+    })?;
+    Ok(Json(events))
+}
+
+/// Whether the client already holds the representation last modified at `last_modified`;
+/// If-Modified-Since only counts without If-None-Match, as its dates stop at seconds.
+fn not_modified(headers: &HeaderMap, last_modified: Option<DateTime<Utc>>) -> bool {
+    let Some(last_modified) = last_modified else {
+        return false;
+    };
+    // decoding If-None-Match succeeds even without the header
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return headers
+            .typed_get::<IfNoneMatch>()
+            .is_some_and(|none_match| !none_match.precondition_passes(&etag(last_modified)));
+    }
+    headers
+        .typed_get::<IfModifiedSince>()
+        .is_some_and(|since| !since.is_modified(SystemTime::from(last_modified)))
+}
+
+/// A strong validator for the representation last modified at `last_modified`, which unlike
+/// Last-Modified tells apart changes within the same second.
+fn etag(last_modified: DateTime<Utc>) -> ETag {
+    format!(
+        "\"{}.{:09}\"",
+        last_modified.timestamp(),
+        last_modified.timestamp_subsec_nanos()
+    )
+    .parse()
+    .expect("a quoted number is a valid entity tag")
+}
+
+/// Lets clients keep the response but makes them revalidate it before every use.
+fn cached(
+    status: StatusCode,
+    last_modified: Option<DateTime<Utc>>,
+    body: impl IntoResponse,
+) -> Response {
+    let validators = last_modified.map(|time| {
+        (
+            TypedHeader(etag(time)),
+            TypedHeader(LastModified::from(SystemTime::from(time))),
+        )
+    });
+    (
+        status,
+        TypedHeader(CacheControl::new().with_no_cache()),
+        validators,
+        body,
+    )
+        .into_response()
 }
 
 pub(crate) async fn delete_todo<T: TodoRepository>(
     Path(id): Path<i32>,
     State(repository): State<Arc<T>>,
 ) -> StatusCode {
-    if repository.delete(id).await.is_ok() {
-        StatusCode::NO_CONTENT
-    } else {
-        StatusCode::NOT_FOUND
+    match repository.delete(id).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => error_status(&e, StatusCode::NOT_FOUND),
     }
 }
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+
+use crate::handlers::auth::AuthUser;
+use crate::handlers::{error_status, ValidatedJson};
+use crate::repositories::webhooks::{CreateWebhook, UpdateWebhook, WebhookRepository};
+
+pub(crate) async fn create_webhook<W: WebhookRepository>(
+    AuthUser(user_id): AuthUser,
+    State(repository): State<Arc<W>>,
+    ValidatedJson(payload): ValidatedJson<CreateWebhook>,
+) -> Result<(StatusCode, impl IntoResponse), StatusCode> {
+    let webhook = repository
+        .create(user_id, payload)
+        .await
+        .map_err(|e| error_status(&e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub(crate) async fn update_webhook<W: WebhookRepository>(
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<i32>,
+    State(repository): State<Arc<W>>,
+    ValidatedJson(payload): ValidatedJson<UpdateWebhook>,
+) -> Result<(StatusCode, impl IntoResponse), StatusCode> {
+    let webhook = repository
+        .update(user_id, id, payload)
+        .await
+        .map_err(|e| error_status(&e, StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub(crate) async fn all_webhook<W: WebhookRepository>(
+    AuthUser(user_id): AuthUser,
+    State(repository): State<Arc<W>>,
+) -> Result<(StatusCode, impl IntoResponse), StatusCode> {
+    let webhooks = repository
+        .all(user_id)
+        .await
+        .map_err(|e| error_status(&e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(webhooks)))
+}
+
+pub(crate) async fn find_webhook<W: WebhookRepository>(
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<i32>,
+    State(repository): State<Arc<W>>,
+) -> Result<(StatusCode, impl IntoResponse), StatusCode> {
+    let webhook = repository
+        .find(user_id, id)
+        .await
+        .map_err(|e| error_status(&e, StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(webhook)))
+}
+
+pub(crate) async fn delete_webhook<W: WebhookRepository>(
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<i32>,
+    State(repository): State<Arc<W>>,
+) -> StatusCode {
+    match repository.delete(user_id, id).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => error_status(&e, StatusCode::NOT_FOUND),
+    }
+}
+
+pub(crate) async fn webhook_deliveries<W: WebhookRepository>(
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<i32>,
+    State(repository): State<Arc<W>>,
+) -> Result<(StatusCode, impl IntoResponse), StatusCode> {
+    let deliveries = repository
+        .deliveries(user_id, id)
+        .await
+        .map_err(|e| error_status(&e, StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(deliveries)))
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::migrate::Migrator;
+use sqlx::{Connection, Database, PgPool, Pool};
+
+use crate::migrations;
+use crate::repositories::event_sourced::EventSourcedRepository;
+use crate::repositories::file::FileRepository;
+
+/// Upper bound for a single check so that a hanging dependency fails the probe instead of
+/// stalling it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A dependency the service needs in order to handle requests.
+#[async_trait]
+pub(crate) trait HealthCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns details worth reporting when the dependency is up.
+    async fn check(&self) -> anyhow::Result<serde_json::Value>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CheckReport {
+    pub(crate) status: Status,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    details: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ReadinessReport {
+    pub(crate) status: Status,
+    pub(crate) checks: BTreeMap<&'static str, CheckReport>,
+}
+
+/// The checks behind `/readyz`; the service is ready when all of them are up.
+#[derive(Clone, Default)]
+pub(crate) struct Readiness {
+    checks: Vec<Arc<dyn HealthCheck>>,
+}
+
+impl Readiness {
+    pub(crate) fn new(checks: Vec<Arc<dyn HealthCheck>>) -> Self {
+        Self { checks }
+    }
+
+    pub(crate) async fn report(&self) -> ReadinessReport {
+        let reports = futures_util::future::join_all(self.checks.iter().map(|check| async move {
+            let started = Instant::now();
+            let result = tokio::time::timeout(CHECK_TIMEOUT, check.check())
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out after {:?}", CHECK_TIMEOUT)));
+            let latency_ms = started.elapsed().as_millis();
+            let report = match result {
+                Ok(details) => CheckReport {
+                    status: Status::Up,
+                    latency_ms,
+                    error: None,
+                    details,
+                },
+                Err(e) => {
+                    tracing::warn!("readiness check {} failed: {:#}", check.name(), e);
+                    CheckReport {
+                        status: Status::Down,
+                        latency_ms,
+                        error: Some(format!("{:#}", e)),
+                        details: serde_json::Value::Null,
+                    }
+                }
+            };
+            (check.name(), report)
+        }))
+        .await;
+        let checks: BTreeMap<_, _> = reports.into_iter().collect();
+        let status = if checks.values().all(|report| report.status == Status::Up) {
+            Status::Up
+        } else {
+            Status::Down
+        };
+        ReadinessReport { status, checks }
+    }
+}
+
+/// Pings the database on a pooled connection.
+pub(crate) struct DatabaseCheck<DB: Database> {
+    pool: Pool<DB>,
+}
+
+impl<DB: Database> DatabaseCheck<DB> {
+    pub(crate) fn new(pool: Pool<DB>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl<DB: Database> HealthCheck for DatabaseCheck<DB> {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn check(&self) -> anyhow::Result<serde_json::Value> {
+        self.pool.acquire().await?.ping().await?;
+        Ok(json!({
+            "connections": self.pool.size(),
+            "idle_connections": self.pool.num_idle(),
+        }))
+    }
+}
+
+/// Down once a write to the log of a `FileRepository` failed, as no change can be saved.
+pub(crate) struct LogCheck {
+    repository: FileRepository,
+}
+
+impl LogCheck {
+    pub(crate) fn new(repository: FileRepository) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for LogCheck {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn check(&self) -> anyhow::Result<serde_json::Value> {
+        let records = self.repository.check().await?;
+        Ok(json!({ "records_since_snapshot": records }))
+    }
+}
+
+/// Down once a write to the event log of an `EventSourcedRepository` failed, like `LogCheck`.
+pub(crate) struct EventLogCheck {
+    repository: EventSourcedRepository,
+}
+
+impl EventLogCheck {
+    pub(crate) fn new(repository: EventSourcedRepository) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for EventLogCheck {
+    fn name(&self) -> &'static str {
+        "event_log"
+    }
+
+    async fn check(&self) -> anyhow::Result<serde_json::Value> {
+        let events = self.repository.check().await?;
+        Ok(json!({ "events_since_snapshot": events }))
+    }
+}
+
+/// Compares the migrations applied to the database with the ones this build ships.
+///
+/// The check is down while a migration is pending or left dirty by a failed run.
+pub(crate) struct MigrationsCheck {
+    pool: PgPool,
+    migrator: &'static Migrator,
+}
+
+impl MigrationsCheck {
+    pub(crate) fn new(pool: PgPool, migrator: &'static Migrator) -> Self {
+        Self { pool, migrator }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for MigrationsCheck {
+    fn name(&self) -> &'static str {
+        "migrations"
+    }
+
+    async fn check(&self) -> anyhow::Result<serde_json::Value> {
+        let mut conn = self.pool.acquire().await?;
+        let applied = migrations::applied(&mut conn).await?;
+        let pending = migrations::pending(self.migrator, &applied);
+        if !pending.is_empty() {
+            anyhow::bail!("pending migrations: {:?}", pending);
+        }
+        Ok(json!({ "applied": applied.len() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StubCheck(&'static str, bool);
+
+    #[async_trait]
+    impl HealthCheck for StubCheck {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn check(&self) -> anyhow::Result<serde_json::Value> {
+            if self.1 {
+                Ok(serde_json::Value::Null)
+            } else {
+                anyhow::bail!("unreachable")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ready_when_every_check_is_up() {
+        let readiness = Readiness::new(vec![
+            Arc::new(StubCheck("database", true)),
+            Arc::new(StubCheck("migrations", true)),
+        ]);
+        let report = readiness.report().await;
+        assert_eq!(Status::Up, report.status);
+        assert_eq!(2, report.checks.len());
+    }
+
+    #[tokio::test]
+    async fn not_ready_when_a_check_is_down() {
+        let readiness = Readiness::new(vec![
+            Arc::new(StubCheck("database", false)),
+            Arc::new(StubCheck("migrations", true)),
+        ]);
+        let report = readiness.report().await;
+        assert_eq!(Status::Down, report.status);
+        assert_eq!(Status::Down, report.checks["database"].status);
+        assert_eq!(
+            Some("unreachable"),
+            report.checks["database"].error.as_deref()
+        );
+        assert_eq!(Status::Up, report.checks["migrations"].status);
+    }
+}
@@ -1,55 +1,651 @@
-use std::env;
-use std::net::SocketAddr;
+use std::any::Any;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::Arc;
 
+use anyhow::Context;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
+use axum::http::{HeaderName, Method, StatusCode};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
 use axum::routing::Router;
 use axum::routing::{get, patch, post};
+use axum::{BoxError, Extension};
+use clap::Parser;
 use dotenv::dotenv;
-use sqlx::PgPool;
+use futures_util::future::BoxFuture;
+use futures_util::{FutureExt, TryFutureExt};
+use sqlx::{PgPool, SqlitePool};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::error::Overloaded;
+use tower::load_shed::LoadShedLayer;
+use tower::ServiceBuilder;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::compression::predicate::{NotForContentType, Predicate};
+use tower_http::compression::{CompressionLayer, DefaultPredicate};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
 
-use crate::handlers::todos::{all_todo, create_todo, delete_todo, find_todo, update_todo};
-use crate::repositories::postgres::PostgresRepository;
+use crate::cli::{Cli, Command};
+use crate::config::{Backend, Config, CorsConfig, LogFormat};
+use crate::consistency::{read_your_writes, ReadYourWrites};
+use crate::events::EventBus;
+use crate::handlers::auth::{JwtKeys, Users};
+use crate::handlers::events::todo_events;
+use crate::handlers::health::{healthz, readyz};
+use crate::handlers::metrics::render_metrics;
+use crate::handlers::todos::{
+    all_todo, create_todo, delete_todo, find_todo, todo_history, update_todo,
+};
+use crate::handlers::webhooks::{
+    all_webhook, create_webhook, delete_webhook, find_webhook, update_webhook, webhook_deliveries,
+};
+use crate::health::{DatabaseCheck, EventLogCheck, LogCheck, MigrationsCheck, Readiness};
+use crate::metrics::{track_requests, Metrics};
+use crate::outbox::{NotifySink, OutboxRelay};
+use crate::rate_limit::{limit_requests, InMemoryStore, RateLimiter};
+use crate::repositories::event_sourced::EventSourcedRepository;
+use crate::repositories::file::FileRepository;
+use crate::repositories::hash_map::HashMapRepository;
+use crate::repositories::postgres::{self, PostgresRepository};
+use crate::repositories::sqlite::{self, SqliteRepository};
+use crate::repositories::todos::cached::CachedRepository;
+use crate::repositories::todos::instrumented::InstrumentedRepository;
 use crate::repositories::todos::TodoRepository;
+use crate::repositories::Transactional;
+use crate::shutdown::ShutdownSignal;
+use crate::tls::CertificateReloader;
+use crate::webhooks::queue::{QueueWorker, WebhookQueue};
+use crate::webhooks::{RetryPolicy, WebhookDispatcher};
 
+mod cli;
+mod config;
+mod consistency;
+mod events;
 mod handlers;
+mod health;
+mod metrics;
+mod migrations;
+mod outbox;
+mod rate_limit;
 mod repositories;
+mod shutdown;
+mod telemetry;
+mod tls;
+mod webhooks;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
     dotenv().ok();
-    let log_level = env::var("RUST_LOG").unwrap_or("debug".to_string());
-    env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
-    let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be defined");
-    tracing::debug!("start connecting to the database...");
-    let pool = PgPool::connect(database_url).await.unwrap_or_else(|_| {
-        panic!(
-            "failed to connect to the database whose url is: [{}]",
-            database_url
-        )
+    let config = Config::load().context("failed to load the configuration")?;
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            config.auth.require_secret()?;
+            init_tracing(&config, BoxMakeWriter::new(io::stdout))?;
+            serve_api(config).await
+        }
+        Command::Admin(command) => {
+            // standard output is for what the command prints, e.g. an export
+            init_tracing(&config, BoxMakeWriter::new(io::stderr))?;
+            cli::run(command, &config).await
+        }
+    }
+}
+
+/// What runs next to the app whatever the backend, shared with the parts that need it.
+struct Runtime {
+    shutdown: CancellationToken,
+    tasks: TaskTracker,
+    events: EventBus,
+    metrics: Metrics,
+}
+
+async fn serve_api(config: Config) -> anyhow::Result<()> {
+    let shutdown_signal = ShutdownSignal::install().context("failed to install signal handlers")?;
+    let runtime = Runtime {
+        shutdown: CancellationToken::new(),
+        tasks: TaskTracker::new(),
+        events: EventBus::default(),
+        metrics: Metrics::new(),
+    };
+    let backend = config
+        .database
+        .backend()
+        .context("unsupported database url")?;
+    match backend {
+        Backend::Postgres => {
+            let (app, pool) = postgres_app(&config, &runtime).await?;
+            run_until_shutdown(app, &config, runtime, shutdown_signal).await?;
+            pool.close().await;
+        }
+        Backend::Sqlite => {
+            let (app, pool) = sqlite_app(&config, &runtime).await?;
+            run_until_shutdown(app, &config, runtime, shutdown_signal).await?;
+            pool.close().await;
+        }
+        Backend::Memory => {
+            tracing::warn!("keeping data in memory, it is lost when the process exits");
+            let app = memory_app(&config, &runtime)?;
+            run_until_shutdown(app, &config, runtime, shutdown_signal).await?;
+        }
+        Backend::File => {
+            let app = file_app(&config, &runtime).await?;
+            run_until_shutdown(app, &config, runtime, shutdown_signal).await?;
+        }
+        Backend::Events => {
+            let app = events_app(&config, &runtime).await?;
+            run_until_shutdown(app, &config, runtime, shutdown_signal).await?;
+        }
+    }
+    #[cfg(feature = "otel")]
+    tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await?;
+    tracing::info!("shut down");
+    Ok(())
+}
+
+async fn postgres_app(config: &Config, runtime: &Runtime) -> anyhow::Result<(Router, PgPool)> {
+    let pool = postgres::connect(&config.database).await?;
+    let replicas = config
+        .database
+        .replica_urls
+        .iter()
+        .map(|url| postgres::connect_replica(&config.database.replica(url)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if config.database.run_migrations {
+        migrations::run(&pool, &postgres::MIGRATOR)
+            .await
+            .context("failed to apply the migrations")?;
+    } else {
+        migrations::ensure_known(&pool, &postgres::MIGRATOR)
+            .await
+            .context("refusing to start")?;
+    }
+    runtime.tasks.spawn({
+        let pool = pool.clone();
+        let events = runtime.events.clone();
+        let shutdown = runtime.shutdown.clone();
+        async move {
+            if let Err(e) = events::listener::listen(pool, events, shutdown).await {
+                tracing::error!("stopped listening for todo events: {}", e);
+            }
+        }
+    });
+    runtime
+        .metrics
+        .register_pool(pool.clone())
+        .context("failed to register the pool metrics")?;
+    let repository = Arc::new(PostgresRepository::new(pool.clone()));
+    let dispatcher = WebhookDispatcher::new(
+        Arc::clone(&repository),
+        RetryPolicy::default(),
+        &config.webhooks,
+        runtime.tasks.clone(),
+        runtime.shutdown.clone(),
+    )?;
+    let relay = OutboxRelay::new(
+        pool.clone(),
+        vec![Arc::new(NotifySink), Arc::new(WebhookQueue)],
+    );
+    runtime.tasks.spawn(relay.run(runtime.shutdown.clone()));
+    let worker = QueueWorker::new(pool.clone(), dispatcher);
+    runtime.tasks.spawn(worker.run(runtime.shutdown.clone()));
+    let readiness = Readiness::new(vec![
+        Arc::new(DatabaseCheck::new(pool.clone())),
+        Arc::new(MigrationsCheck::new(pool.clone(), &postgres::MIGRATOR)),
+    ]);
+    let todos = Arc::new(InstrumentedRepository::new(
+        PostgresRepository::new(pool.clone()).with_replicas(replicas),
+        runtime.metrics.clone(),
+    ));
+    let app = create_app(
+        todos,
+        repository,
+        runtime.events.clone(),
+        readiness,
+        runtime.metrics.clone(),
+        &runtime.tasks,
+        config,
+    );
+    Ok((app, pool))
+}
+
+/// The app on a SQLite database, which is migrated as it is opened.
+async fn sqlite_app(config: &Config, runtime: &Runtime) -> anyhow::Result<(Router, SqlitePool)> {
+    let pool = sqlite::connect(&config.database).await?;
+    runtime
+        .metrics
+        .register_pool(pool.clone())
+        .context("failed to register the pool metrics")?;
+    let repository = Arc::new(SqliteRepository::new(pool.clone(), runtime.events.clone()));
+    let dispatcher = WebhookDispatcher::new(
+        Arc::clone(&repository),
+        RetryPolicy::default(),
+        &config.webhooks,
+        runtime.tasks.clone(),
+        runtime.shutdown.clone(),
+    )?;
+    runtime.tasks.spawn(events::forward(
+        runtime.events.subscribe(),
+        Arc::new(dispatcher),
+        runtime.shutdown.clone(),
+    ));
+    let readiness = Readiness::new(vec![Arc::new(DatabaseCheck::new(pool.clone()))]);
+    let todos = Arc::new(InstrumentedRepository::new(
+        SqliteRepository::new(pool.clone(), runtime.events.clone()),
+        runtime.metrics.clone(),
+    ));
+    let app = create_app(
+        todos,
+        repository,
+        runtime.events.clone(),
+        readiness,
+        runtime.metrics.clone(),
+        &runtime.tasks,
+        config,
+    );
+    Ok((app, pool))
+}
+
+/// The app on data that lives as long as the process, with nothing to check for readiness.
+fn memory_app(config: &Config, runtime: &Runtime) -> anyhow::Result<Router> {
+    let repository = Arc::new(HashMapRepository::with_events(runtime.events.clone()));
+    let dispatcher = WebhookDispatcher::new(
+        Arc::clone(&repository),
+        RetryPolicy::default(),
+        &config.webhooks,
+        runtime.tasks.clone(),
+        runtime.shutdown.clone(),
+    )?;
+    runtime.tasks.spawn(events::forward(
+        runtime.events.subscribe(),
+        Arc::new(dispatcher),
+        runtime.shutdown.clone(),
+    ));
+    // clones share the data, so todos and webhooks see the same users
+    let todos = Arc::new(InstrumentedRepository::new(
+        (*repository).clone(),
+        runtime.metrics.clone(),
+    ));
+    Ok(create_app(
+        todos,
+        repository,
+        runtime.events.clone(),
+        Readiness::default(),
+        runtime.metrics.clone(),
+        &runtime.tasks,
+        config,
+    ))
+}
+
+/// The app on a directory of files, which is locked for as long as the app lives.
+async fn file_app(config: &Config, runtime: &Runtime) -> anyhow::Result<Router> {
+    let repository =
+        Arc::new(FileRepository::open(&config.database, runtime.events.clone()).await?);
+    let dispatcher = WebhookDispatcher::new(
+        Arc::clone(&repository),
+        RetryPolicy::default(),
+        &config.webhooks,
+        runtime.tasks.clone(),
+        runtime.shutdown.clone(),
+    )?;
+    runtime.tasks.spawn(events::forward(
+        runtime.events.subscribe(),
+        Arc::new(dispatcher),
+        runtime.shutdown.clone(),
+    ));
+    let readiness = Readiness::new(vec![Arc::new(LogCheck::new((*repository).clone()))]);
+    let todos = Arc::new(InstrumentedRepository::new(
+        (*repository).clone(),
+        runtime.metrics.clone(),
+    ));
+    Ok(create_app(
+        todos,
+        repository,
+        runtime.events.clone(),
+        readiness,
+        runtime.metrics.clone(),
+        &runtime.tasks,
+        config,
+    ))
+}
+
+/// The app on a directory of files like `file_app`, with the todos kept as their events in a
+/// subdirectory of their own.
+async fn events_app(config: &Config, runtime: &Runtime) -> anyhow::Result<Router> {
+    let repository =
+        Arc::new(FileRepository::open(&config.database, runtime.events.clone()).await?);
+    let todos = EventSourcedRepository::open(&config.database, runtime.events.clone()).await?;
+    let dispatcher = WebhookDispatcher::new(
+        Arc::clone(&repository),
+        RetryPolicy::default(),
+        &config.webhooks,
+        runtime.tasks.clone(),
+        runtime.shutdown.clone(),
+    )?;
+    runtime.tasks.spawn(events::forward(
+        runtime.events.subscribe(),
+        Arc::new(dispatcher),
+        runtime.shutdown.clone(),
+    ));
+    let readiness = Readiness::new(vec![
+        Arc::new(LogCheck::new((*repository).clone())),
+        Arc::new(EventLogCheck::new(todos.clone())),
+    ]);
+    let todos = Arc::new(InstrumentedRepository::new(todos, runtime.metrics.clone()));
+    Ok(create_app(
+        todos,
+        repository,
+        runtime.events.clone(),
+        readiness,
+        runtime.metrics.clone(),
+        &runtime.tasks,
+        config,
+    ))
+}
+
+/// Serves `app` until a shutdown signal, then waits for requests and background tasks to
+/// finish, up to the shutdown timeout.
+async fn run_until_shutdown(
+    app: Router,
+    config: &Config,
+    runtime: Runtime,
+    shutdown_signal: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let Runtime {
+        shutdown,
+        tasks,
+        events,
+        ..
+    } = runtime;
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal.recv().await;
+            tracing::info!("shutting down...");
+            shutdown.cancel();
+            events.close();
+        }
+    });
+    let server = serve(app, config, &tasks, &shutdown).await?;
+    let drained = async {
+        server.await?;
+        tasks.close();
+        tasks.wait().await;
+        anyhow::Ok(())
+    };
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(config.server.shutdown_timeout()).await;
+    };
+    tokio::select! {
+        result = drained => result?,
+        _ = deadline => tracing::warn!(
+            "requests and workers still running after {:?}, shutting down anyway",
+            config.server.shutdown_timeout()
+        ),
+    }
+    Ok(())
+}
+
+/// Binds the listeners up front and returns the server, which stops accepting connections
+/// once `shutdown` is cancelled.
+async fn serve(
+    app: Router,
+    config: &Config,
+    tasks: &TaskTracker,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<BoxFuture<'static, anyhow::Result<()>>> {
+    let addr = config.server.addr();
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to bind to {}", addr))?;
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    if !config.tls.enabled() {
+        tracing::debug!("listening on {}", addr);
+        let server = axum::Server::from_tcp(listener)?
+            .serve(app)
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+        return Ok(server.map_err(anyhow::Error::from).boxed());
+    }
+
+    let certificates = CertificateReloader::load(&config.tls)
+        .await
+        .context("failed to load the TLS certificate")?;
+    let handle = axum_server::Handle::new();
+    let server = axum_server::from_tcp_rustls(listener, certificates.config())
+        .handle(handle.clone())
+        .serve(app);
+    tracing::debug!("listening on {} with TLS", addr);
+    tasks.spawn(certificates.watch(shutdown.clone()));
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.cancelled().await;
+            handle.graceful_shutdown(None);
+        }
     });
-    let repository = PostgresRepository::new(pool);
-    let app = create_app(repository.into());
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::debug!("listening on {}", addr);
-    tracing::debug!("http://localhost:3000");
-    Ok(axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?)
+    if config.tls.redirect_port != 0 {
+        let addr = SocketAddr::new(config.server.host, config.tls.redirect_port);
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("failed to bind to {}", addr))?;
+        let redirect = axum::Server::from_tcp(listener)?
+            .serve(tls::redirect_app(config.server.port).into_make_service())
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+        tracing::debug!("redirecting {} to HTTPS", addr);
+        tasks.spawn(async move {
+            if let Err(e) = redirect.await {
+                tracing::error!("stopped redirecting to HTTPS: {}", e);
+            }
+        });
+    }
+    Ok(server.map_err(anyhow::Error::from).boxed())
 }
 
-fn create_app<T: TodoRepository>(repository: Arc<T>) -> Router {
+fn init_tracing(config: &Config, writer: BoxMakeWriter) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(&config.log.level)?;
+    let (text, json) = match config.log.format {
+        LogFormat::Text => (
+            Some(tracing_subscriber::fmt::layer().with_writer(writer)),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(tracing_subscriber::fmt::layer().json().with_writer(writer)),
+        ),
+    };
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json);
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let otel = if config.telemetry.otlp_endpoint.is_empty() {
+            None
+        } else {
+            let provider = telemetry::otel::tracer_provider(&config.telemetry)?;
+            let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+            opentelemetry::global::set_tracer_provider(provider);
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        };
+        registry.with(otel).init();
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.init();
+        if !config.telemetry.otlp_endpoint.is_empty() {
+            tracing::warn!("built without the otel feature, traces are not exported");
+        }
+    }
+    Ok(())
+}
+
+fn create_app<T, W>(
+    repository: Arc<T>,
+    webhooks: Arc<W>,
+    events: EventBus,
+    readiness: Readiness,
+    metrics: Metrics,
+    tasks: &TaskTracker,
+    config: &Config,
+) -> Router
+where
+    T: TodoRepository,
+    // where handlers run transactions: the todos of the events backend cannot take part in
+    // one, while the repository of users and webhooks can on every backend
+    W: repositories::webhooks::WebhookRepository + Transactional,
+{
+    let keys = Arc::new(JwtKeys::new(config.auth.jwt_secret.as_bytes()));
+    let users: Users = webhooks.clone();
+    let mut repository =
+        CachedRepository::new((*repository).clone(), &config.cache, metrics.clone());
+    if !config.database.replica_urls.is_empty() {
+        repository = repository.with_replica_lag(config.database.read_your_writes());
+    }
+    tasks.spawn(repository.invalidate_on(&events));
+    let repository = Arc::new(repository);
+    let limiter = RateLimiter::new(
+        config.rate_limit.clone(),
+        Arc::new(InMemoryStore::default()),
+        Arc::clone(&keys),
+    );
+    // probed by infrastructure rather than clients, so neither limited nor shed: a busy
+    // instance failing its liveness probe would be restarted just when it is needed
+    let probes = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(readiness)
+        .route("/metrics", get(render_metrics::<CachedRepository<T>>))
+        .with_state(Arc::clone(&repository))
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(TimeoutLayer::new(config.server.request_timeout()));
     Router::new()
         .route("/", get(root))
-        .route("/todos", post(create_todo::<T>).get(all_todo::<T>))
+        .route("/todos/events", get(todo_events))
+        .with_state(events)
+        .route(
+            "/todos",
+            post(create_todo::<CachedRepository<T>>).get(all_todo::<CachedRepository<T>>),
+        )
         .with_state(Arc::clone(&repository))
         .route(
             "/todos/:id",
-            patch(update_todo::<T>)
-                .get(find_todo::<T>)
-                .delete(delete_todo::<T>),
+            patch(update_todo::<CachedRepository<T>>)
+                .get(find_todo::<CachedRepository<T>>)
+                .delete(delete_todo::<CachedRepository<T>>),
+        )
+        .route(
+            "/todos/:id/events",
+            get(todo_history::<CachedRepository<T>>),
         )
         .with_state(Arc::clone(&repository))
+        .route("/webhooks", post(create_webhook::<W>).get(all_webhook::<W>))
+        .route(
+            "/webhooks/:id",
+            patch(update_webhook::<W>)
+                .get(find_webhook::<W>)
+                .delete(delete_webhook::<W>),
+        )
+        .route("/webhooks/:id/deliveries", get(webhook_deliveries::<W>))
+        .with_state(webhooks)
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(TimeoutLayer::new(config.server.request_timeout()))
+        .layer(middleware::from_fn_with_state(
+            ReadYourWrites::new(&config.database, Arc::clone(&keys)),
+            read_your_writes,
+        ))
+        .layer(middleware::from_fn_with_state(limiter, limit_requests))
+        .layer(Extension(keys))
+        .layer(Extension(users))
+        // the body limit applies to the decompressed body, which keeps zip bombs out
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_middleware_error))
+                .layer(RequestDecompressionLayer::new()),
+        )
+        .layer(DefaultBodyLimit::max(config.server.body_limit))
+        .layer(CompressionLayer::new().compress_when(
+            // buffering in the encoder would hold back events until it is flushed
+            DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream")),
+        ))
+        .layer(
+            // one semaphore for the whole router, since every route gets its own layer
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_middleware_error))
+                .layer(LoadShedLayer::new())
+                .layer(GlobalConcurrencyLimitLayer::with_semaphore(Arc::new(
+                    Semaphore::new(config.server.max_concurrent_requests),
+                ))),
+        )
+        .merge(probes)
+        .layer(cors_layer(&config.cors))
+        .layer(Extension(metrics.clone()))
+        .layer(middleware::from_fn_with_state(metrics, track_requests))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(telemetry::make_span)
+                        .on_response(telemetry::on_response),
+                )
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
+}
+
+fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    // every value is validated when the configuration is loaded
+    let origins = config
+        .allowed_origins
+        .iter()
+        .filter_map(|origin| origin.parse().ok());
+    let methods = config
+        .allowed_methods
+        .iter()
+        .filter_map(|method| method.parse::<Method>().ok())
+        .collect::<Vec<_>>();
+    let headers = config
+        .allowed_headers
+        .iter()
+        .filter_map(|name| name.parse::<HeaderName>().ok())
+        .collect::<Vec<_>>();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(config.allow_credentials)
+        .max_age(config.max_age())
+}
+
+async fn handle_middleware_error(err: BoxError) -> (StatusCode, &'static str) {
+    if err.is::<Overloaded>() {
+        (StatusCode::SERVICE_UNAVAILABLE, "Server is overloaded")
+    } else {
+        tracing::error!("unhandled middleware error: {}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+fn handle_panic(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    tracing::error!("handler panicked: {}", message);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
 }
 
 async fn root() -> &'static str {
@@ -58,18 +654,28 @@ async fn root() -> &'static str {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use axum::async_trait;
     use axum::body::Body;
     use axum::http;
-    use axum::http::{header, Method, StatusCode};
+    use axum::http::{header, HeaderValue, Method, StatusCode};
     use axum::response::Response;
+    use flate2::read::GzDecoder;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
     use http::Request;
-    use hyper::body::to_bytes;
+    use hyper::body::{to_bytes, HttpBody};
     use serde::Deserialize;
     use serde_json::json;
     use tower::ServiceExt;
 
-    use crate::repositories::hash_map::test_utils::HashMapRepository;
+    use crate::events::{TodoEvent, TodoEventKind};
     use crate::repositories::todos::{CreateTodo, Todo};
+    use crate::repositories::users::{CreateUser, UpdateUser};
+    use crate::repositories::webhooks::{CreateWebhook, Webhook};
 
     use super::*;
 
@@ -85,18 +691,197 @@ mod tests {
             .body(Body::from(json_body))
     }
 
+    fn build_authorized_request_with_json(
+        path: &str,
+        method: Method,
+        json_body: String,
+        user_id: i32,
+    ) -> http::Result<Request<Body>> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(header::AUTHORIZATION, bearer(user_id))
+            .body(Body::from(json_body))
+    }
+
+    const JWT_SECRET: &str = "secret";
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.auth.jwt_secret = JWT_SECRET.to_string();
+        config
+    }
+
+    fn test_app<R>(repository: R) -> Router
+    where
+        R: repositories::webhooks::WebhookRepository + Transactional,
+    {
+        create_app(
+            repository.clone().into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &test_config(),
+        )
+    }
+
+    /// Creates the users with ids `1..=count` that tokens are issued to.
+    async fn create_users(repository: &HashMapRepository, count: i32) {
+        use crate::repositories::users::UserRepository;
+
+        for i in 1..=count {
+            let user = UserRepository::create(
+                repository,
+                CreateUser {
+                    username: format!("user{}", i),
+                    email: format!("user{}@example.com", i),
+                    password_hash: "hash".to_string(),
+                },
+            )
+            .await
+            .expect("failed to create user");
+            assert_eq!(i, user.id);
+        }
+    }
+
+    fn bearer(user_id: i32) -> String {
+        let token = JwtKeys::new(JWT_SECRET.as_bytes())
+            .issue(user_id, chrono::Duration::minutes(5))
+            .unwrap();
+        format!("Bearer {}", token)
+    }
+
+    /// A backend the handler tests that touch the repository run against.
+    #[async_trait]
+    trait TestBackend: TodoRepository {
+        /// An empty repository whose clock stands at `start_time()`, keeping any files in `dir`.
+        async fn open(dir: &Path) -> Self;
+
+        fn set_now(&self, now: chrono::DateTime<chrono::Utc>);
+
+        fn app(self) -> Router;
+    }
+
+    #[async_trait]
+    impl TestBackend for HashMapRepository {
+        async fn open(_: &Path) -> Self {
+            HashMapRepository::new()
+        }
+
+        fn set_now(&self, now: chrono::DateTime<chrono::Utc>) {
+            HashMapRepository::set_now(self, now)
+        }
+
+        fn app(self) -> Router {
+            test_app(self)
+        }
+    }
+
+    #[async_trait]
+    impl TestBackend for SqliteRepository {
+        async fn open(_: &Path) -> Self {
+            repositories::sqlite::test_utils::in_memory().await
+        }
+
+        fn set_now(&self, now: chrono::DateTime<chrono::Utc>) {
+            SqliteRepository::set_now(self, now)
+        }
+
+        fn app(self) -> Router {
+            test_app(self)
+        }
+    }
+
+    #[async_trait]
+    impl TestBackend for FileRepository {
+        async fn open(dir: &Path) -> Self {
+            repositories::file::test_utils::open(dir, 1000).await
+        }
+
+        fn set_now(&self, now: chrono::DateTime<chrono::Utc>) {
+            FileRepository::set_now(self, now)
+        }
+
+        fn app(self) -> Router {
+            test_app(self)
+        }
+    }
+
+    #[async_trait]
+    impl TestBackend for EventSourcedRepository {
+        async fn open(dir: &Path) -> Self {
+            repositories::event_sourced::test_utils::open(dir, 1000).await
+        }
+
+        fn set_now(&self, now: chrono::DateTime<chrono::Utc>) {
+            EventSourcedRepository::set_now(self, now)
+        }
+
+        fn app(self) -> Router {
+            create_app(
+                self.into(),
+                HashMapRepository::new().into(),
+                EventBus::default(),
+                Readiness::default(),
+                Metrics::default(),
+                &TaskTracker::new(),
+                &test_config(),
+            )
+        }
+    }
+
+    /// Declares a module named after the backend with a test running each generic test on it.
+    macro_rules! backend_tests {
+        (@tests $repository:ty: $($test:ident),* $(,)?) => {
+            $(
+                #[tokio::test]
+                async fn $test() -> http::Result<()> {
+                    super::$test::<$repository>().await
+                }
+            )*
+        };
+        ($backend:ident: $repository:ty) => {
+            mod $backend {
+                use super::*;
+
+                backend_tests!(
+                    @tests $repository:
+                    create_todo,
+                    post_validation_empty,
+                    post_validation_too_long_text,
+                    update_todo,
+                    get_all_todos,
+                    find_todos,
+                    not_found_todos,
+                    delete_todo,
+                    not_deleted_todo,
+                    compress_todo_list,
+                    answer_not_modified_until_todo_changes,
+                    answer_change_within_the_same_second,
+                );
+            }
+        };
+    }
+
+    backend_tests!(hash_map: HashMapRepository);
+    backend_tests!(sqlite: SqliteRepository);
+    backend_tests!(file: FileRepository);
+    backend_tests!(event_sourced: EventSourcedRepository);
+
     async fn response_to_result<T: for<'a> Deserialize<'a>>(res: Response) -> T {
         let bytes = to_bytes(res.into_body()).await.unwrap();
         let body = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo = serde_json::from_str(&body).unwrap();
-        todo
+        serde_json::from_str(&body).unwrap()
     }
 
     #[tokio::test]
     async fn hello_world() {
         let req = Request::builder().uri("/").body(Body::empty()).unwrap();
         let repository = HashMapRepository::new();
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = test_app(repository).oneshot(req).await.unwrap();
         assert_eq!(StatusCode::OK, res.status());
 
         let bytes = to_bytes(res.into_body()).await.unwrap();
@@ -104,15 +889,15 @@ mod tests {
         assert_eq!(body, "Hello, World!");
     }
 
-    #[tokio::test]
-    async fn create_todo() -> http::Result<()> {
-        let repository = HashMapRepository::new();
+    async fn create_todo<R: TestBackend>() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         let req = build_request_with_json(
             "/todos",
             Method::POST,
             r#"{"text": "todo","completed": false}"#.to_string(),
         )?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::CREATED, res.status());
         let todo = response_to_result::<Todo>(res).await;
         let expected = Todo::new(1, "todo".to_string());
@@ -120,15 +905,15 @@ mod tests {
         Ok(())
     }
 
-    #[tokio::test]
-    async fn post_validation_empty() -> http::Result<()> {
-        let repository = HashMapRepository::new();
+    async fn post_validation_empty<R: TestBackend>() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         let req = build_request_with_json(
             "/todos",
             Method::POST,
             r#"{"text": "","completed": false}"#.to_string(),
         )?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::BAD_REQUEST, res.status());
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body = String::from_utf8(bytes.to_vec()).unwrap();
@@ -136,9 +921,9 @@ mod tests {
         Ok(())
     }
 
-    #[tokio::test]
-    async fn post_validation_too_long_text() -> http::Result<()> {
-        let repository = HashMapRepository::new();
+    async fn post_validation_too_long_text<R: TestBackend>() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         let text = "a".repeat(101);
         let body = json!({
             "text": text,
@@ -146,7 +931,7 @@ mod tests {
         })
         .to_string();
         let req = build_request_with_json("/todos", Method::POST, body)?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::BAD_REQUEST, res.status());
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body = String::from_utf8(bytes.to_vec()).unwrap();
@@ -157,11 +942,12 @@ mod tests {
         Ok(())
     }
 
-    #[tokio::test]
-    async fn update_todo() -> http::Result<()> {
+    async fn update_todo<R: TestBackend>() -> http::Result<()> {
         let expected = Todo::new(1, "should_update_todo".to_string());
 
-        let repository = HashMapRepository::new();
+        let dir = tempfile::tempdir().unwrap();
+
+        let repository = R::open(dir.path()).await;
         repository
             .create(CreateTodo::new("before_update_todo".to_string()))
             .await
@@ -171,54 +957,54 @@ mod tests {
             Method::PATCH,
             r#"{"text": "should_update_todo","completed": false}"#.to_string(),
         )?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::CREATED, res.status());
         let todo = response_to_result::<Todo>(res).await;
         assert_eq!(expected, todo);
         Ok(())
     }
 
-    #[tokio::test]
-    async fn get_all_todos() -> http::Result<()> {
+    async fn get_all_todos<R: TestBackend>() -> http::Result<()> {
         let payload = CreateTodo::new("temp".to_string());
-        let repository = HashMapRepository::new();
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         repository
             .create(payload)
             .await
             .expect("failed to create todo");
         let req = build_request_with_json("/todos", Method::GET, String::default())?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         let todo = response_to_result::<Vec<Todo>>(res).await;
         assert_eq!(vec![Todo::new(1, "temp".to_string())], todo);
         Ok(())
     }
 
-    #[tokio::test]
-    async fn find_todos() -> http::Result<()> {
+    async fn find_todos<R: TestBackend>() -> http::Result<()> {
         let payload = CreateTodo::new("temp".to_string());
-        let repository = HashMapRepository::new();
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         repository
             .create(payload)
             .await
             .expect("failed to create todo");
         let req = build_request_with_json("/todos/1", Method::GET, String::default())?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::OK, res.status());
         let todo = response_to_result::<Todo>(res).await;
         assert_eq!(Todo::new(1, "temp".to_string()), todo);
         Ok(())
     }
 
-    #[tokio::test]
-    async fn not_found_todos() -> http::Result<()> {
+    async fn not_found_todos<R: TestBackend>() -> http::Result<()> {
         let payload = CreateTodo::new("temp".to_string());
-        let repository = HashMapRepository::new();
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         repository
             .create(payload)
             .await
             .expect("failed to create todo");
         let req = build_request_with_json("/todos/2", Method::GET, String::default())?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::NOT_FOUND, res.status());
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body: String = String::from_utf8(bytes.to_vec()).unwrap();
@@ -226,31 +1012,898 @@ mod tests {
         Ok(())
     }
 
-    #[tokio::test]
-    async fn delete_todo() -> http::Result<()> {
+    async fn delete_todo<R: TestBackend>() -> http::Result<()> {
         let payload = CreateTodo::new("temp".to_string());
-        let repository = HashMapRepository::new();
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         repository
             .create(payload)
             .await
             .expect("failed to create todo");
         let req = build_request_with_json("/todos/1", Method::DELETE, String::default())?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
         assert_eq!(StatusCode::NO_CONTENT, res.status());
         Ok(())
     }
 
-    #[tokio::test]
-    async fn not_deleted_todo() -> http::Result<()> {
+    async fn not_deleted_todo<R: TestBackend>() -> http::Result<()> {
         let payload = CreateTodo::new("temp".to_string());
-        let repository = HashMapRepository::new();
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
         repository
             .create(payload)
             .await
             .expect("failed to create todo");
         let req = build_request_with_json("/todos/2", Method::DELETE, String::default())?;
-        let res = create_app(repository.into()).oneshot(req).await.unwrap();
+        let res = repository.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn track_cache_invalidation_until_events_close() {
+        let events = EventBus::default();
+        let tasks = TaskTracker::new();
+        let _app = create_app(
+            HashMapRepository::new().into(),
+            HashMapRepository::new().into(),
+            events.clone(),
+            Readiness::default(),
+            Metrics::default(),
+            &tasks,
+            &test_config(),
+        );
+        assert_eq!(1, tasks.len());
+
+        events.close();
+        tasks.close();
+        tokio::time::timeout(Duration::from_secs(1), tasks.wait())
+            .await
+            .expect("the invalidation task outlived the events");
+    }
+
+    #[tokio::test]
+    async fn stream_todo_events() -> http::Result<()> {
+        let events = EventBus::default();
+        let req = Request::builder()
+            .uri("/todos/events")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())?;
+        let res = create_app(
+            HashMapRepository::new().into(),
+            HashMapRepository::new().into(),
+            events.clone(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &test_config(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            mime::TEXT_EVENT_STREAM.as_ref(),
+            res.headers()[header::CONTENT_TYPE]
+        );
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+
+        let event = TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: Todo::new(1, "todo".to_string()),
+        };
+        events.publish(event.clone());
+        let mut body = res.into_body();
+        let chunk = body.data().await.unwrap().unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+        assert_eq!(
+            format!(
+                "event:created\ndata:{}\n\n",
+                serde_json::to_string(&event).unwrap()
+            ),
+            chunk
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn webhooks_require_token() -> http::Result<()> {
+        let req = build_request_with_json("/webhooks", Method::GET, String::default())?;
+        let res = test_app(HashMapRepository::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_token_of_disabled_or_deleted_user() -> http::Result<()> {
+        use crate::repositories::users::UserRepository;
+
+        let repository = HashMapRepository::new();
+        create_users(&repository, 2).await;
+        let list = |user_id| {
+            build_authorized_request_with_json("/webhooks", Method::GET, String::default(), user_id)
+        };
+        let res = test_app(repository.clone())
+            .oneshot(list(1)?)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let disable = UpdateUser {
+            disabled: Some(true),
+            ..UpdateUser::default()
+        };
+        UserRepository::update(&repository, 1, disable)
+            .await
+            .expect("failed to disable user");
+        let res = test_app(repository.clone())
+            .oneshot(list(1)?)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        UserRepository::delete(&repository, 2)
+            .await
+            .expect("failed to delete user");
+        let res = test_app(repository).oneshot(list(2)?).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_webhook() -> http::Result<()> {
+        let repository = HashMapRepository::new();
+        create_users(&repository, 2).await;
+        let req = build_authorized_request_with_json(
+            "/webhooks",
+            Method::POST,
+            r#"{"url": "http://localhost/hook", "secret": "0123456789abcdef", "events": ["completed"]}"#
+                .to_string(),
+            1,
+        )?;
+        let res = test_app(repository.clone()).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            json!({
+                "id": 1,
+                "user_id": 1,
+                "url": "http://localhost/hook",
+                "events": ["completed"]
+            }),
+            body
+        );
+
+        let req =
+            build_authorized_request_with_json("/webhooks", Method::GET, String::default(), 2)?;
+        let res = test_app(repository).oneshot(req).await.unwrap();
+        let webhooks = response_to_result::<Vec<Webhook>>(res).await;
+        assert!(webhooks.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn webhook_validation_invalid_url() -> http::Result<()> {
+        let repository = HashMapRepository::new();
+        create_users(&repository, 1).await;
+        let req = build_authorized_request_with_json(
+            "/webhooks",
+            Method::POST,
+            r#"{"url": "not a url", "secret": "0123456789abcdef"}"#.to_string(),
+            1,
+        )?;
+        let res = test_app(repository).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn webhook_validation_other_scheme() -> http::Result<()> {
+        let repository = HashMapRepository::new();
+        create_users(&repository, 1).await;
+        for url in ["ftp://example.com/hook", "file:///etc/passwd"] {
+            let req = build_authorized_request_with_json(
+                "/webhooks",
+                Method::POST,
+                json!({"url": url, "secret": "0123456789abcdef"}).to_string(),
+                1,
+            )?;
+            let res = test_app(repository.clone()).oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn webhook_deliveries_of_other_user() -> http::Result<()> {
+        use crate::repositories::webhooks::WebhookRepository;
+
+        let repository = HashMapRepository::new();
+        create_users(&repository, 2).await;
+        WebhookRepository::create(
+            &repository,
+            1,
+            CreateWebhook::new(
+                "http://localhost/hook".to_string(),
+                "0123456789abcdef".to_string(),
+                vec![],
+            ),
+        )
+        .await
+        .expect("failed to create webhook");
+        let req = build_authorized_request_with_json(
+            "/webhooks/1/deliveries",
+            Method::GET,
+            String::default(),
+            1,
+        )?;
+        let res = test_app(repository.clone()).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_authorized_request_with_json(
+            "/webhooks/1/deliveries",
+            Method::GET,
+            String::default(),
+            2,
+        )?;
+        let res = test_app(repository).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_body_over_limit() -> http::Result<()> {
+        let mut config = test_config();
+        config.server.body_limit = 16;
+        let repository = HashMapRepository::new();
+        let app = create_app(
+            repository.clone().into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &config,
+        );
+        let req = build_request_with_json(
+            "/todos",
+            Method::POST,
+            r#"{"text": "longer than the limit"}"#.to_string(),
+        )?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+        Ok(())
+    }
+
+    async fn compress_todo_list<R: TestBackend>() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
+        for i in 0..50 {
+            repository
+                .create(CreateTodo::new(format!("todo {}", i)))
+                .await
+                .expect("failed to create todo");
+        }
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())?;
+        let res = repository.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("gzip", res.headers()[header::CONTENT_ENCODING]);
+
+        let bytes = to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_reader(GzDecoder::new(&bytes[..])).unwrap();
+        assert_eq!(50, todos.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decompress_request_body() -> http::Result<()> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(br#"{"text": "compressed todo"}"#)
+            .unwrap();
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish().unwrap()))?;
+        let res = test_app(HashMapRepository::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = response_to_result::<Todo>(res).await;
+        assert_eq!(Todo::new(1, "compressed todo".to_string()), todo);
+        Ok(())
+    }
+
+    async fn answer_not_modified_until_todo_changes<R: TestBackend>() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
+        repository
+            .create(CreateTodo::new("temp".to_string()))
+            .await
+            .expect("failed to create todo");
+        let app = repository.clone().app();
+        let get = |path: &str, since: Option<&HeaderValue>| {
+            let mut req = Request::builder().uri(path);
+            if let Some(since) = since {
+                req = req.header(header::IF_MODIFIED_SINCE, since);
+            }
+            req.body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(get("/todos/1", None)?).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("no-cache", res.headers()[header::CACHE_CONTROL]);
+        let last_modified = res.headers()[header::LAST_MODIFIED].clone();
+        assert_eq!("Sat, 01 Jul 2023 00:00:00 GMT", last_modified);
+
+        for path in ["/todos/1", "/todos"] {
+            let req = get(path, Some(&last_modified))?;
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+            assert_eq!(last_modified, res.headers()[header::LAST_MODIFIED]);
+            assert!(to_bytes(res.into_body()).await.unwrap().is_empty());
+        }
+
+        repository.set_now(
+            repositories::hash_map::test_utils::start_time() + chrono::Duration::minutes(1),
+        );
+        repository.delete(1).await.expect("failed to delete todo");
+        let res = app
+            .oneshot(get("/todos", Some(&last_modified))?)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            "Sat, 01 Jul 2023 00:01:00 GMT",
+            res.headers()[header::LAST_MODIFIED]
+        );
+        Ok(())
+    }
+
+    async fn answer_change_within_the_same_second<R: TestBackend>() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = R::open(dir.path()).await;
+        repository
+            .create(CreateTodo::new("temp".to_string()))
+            .await
+            .expect("failed to create todo");
+        let app = repository.clone().app();
+        let get = |path: &str, headers: &[(HeaderName, &HeaderValue)]| {
+            let mut req = Request::builder().uri(path);
+            for (name, value) in headers {
+                req = req.header(name, *value);
+            }
+            req.body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(get("/todos/1", &[])?).await.unwrap();
+        let etag = res.headers()[header::ETAG].clone();
+        let last_modified = res.headers()[header::LAST_MODIFIED].clone();
+        let res = app
+            .clone()
+            .oneshot(get("/todos/1", &[(header::IF_NONE_MATCH, &etag)])?)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        assert_eq!(etag, res.headers()[header::ETAG]);
+
+        repository.set_now(
+            repositories::hash_map::test_utils::start_time() + chrono::Duration::milliseconds(500),
+        );
+        let req = build_request_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        )?;
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        for path in ["/todos/1", "/todos"] {
+            // If-Modified-Since still matches, but the entity tag tells the change apart
+            let headers = [
+                (header::IF_NONE_MATCH, &etag),
+                (header::IF_MODIFIED_SINCE, &last_modified),
+            ];
+            let res = app.clone().oneshot(get(path, &headers)?).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!(last_modified, res.headers()[header::LAST_MODIFIED]);
+            assert_ne!(etag, res.headers()[header::ETAG]);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_todo_events() -> http::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = <EventSourcedRepository as TestBackend>::open(dir.path()).await;
+        let todo = repository
+            .create(CreateTodo::new("temp".to_string()))
+            .await
+            .unwrap();
+        repository.delete(todo.id).await.unwrap();
+        let app = repository.app();
+
+        let req = build_request_with_json("/todos/1/events", Method::GET, String::default())?;
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let events = response_to_result::<serde_json::Value>(res).await;
+        assert_eq!(
+            json!([
+                {
+                    "position": 1,
+                    "todo_id": 1,
+                    "version": 1,
+                    "at": "2023-07-01T00:00:00Z",
+                    "type": "todo_created",
+                    "text": "temp",
+                },
+                {
+                    "position": 2,
+                    "todo_id": 1,
+                    "version": 2,
+                    "at": "2023-07-01T00:00:00Z",
+                    "type": "deleted",
+                },
+            ]),
+            events
+        );
+        let req = build_request_with_json("/todos/2/events", Method::GET, String::default())?;
+        let res = app.oneshot(req).await.unwrap();
         assert_eq!(StatusCode::NOT_FOUND, res.status());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn answer_501_for_events_of_todos_kept_without_history() -> http::Result<()> {
+        let repository = HashMapRepository::new();
+        repository
+            .create(CreateTodo::new("temp".to_string()))
+            .await
+            .unwrap();
+        let req = build_request_with_json("/todos/1/events", Method::GET, String::default())?;
+        let res = test_app(repository).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_IMPLEMENTED, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn allow_configured_origin() -> http::Result<()> {
+        let mut config = test_config();
+        config.cors.allowed_origins = vec!["https://app.example.com".to_string()];
+        let repository = HashMapRepository::new();
+        let app = create_app(
+            repository.clone().into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &config,
+        );
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::ORIGIN, "https://app.example.com")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            "https://app.example.com",
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN]
+        );
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::ORIGIN, "https://evil.example.com")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answer_preflight_for_patch_with_authorization() -> http::Result<()> {
+        let mut config = test_config();
+        config.cors.allowed_origins = vec!["https://app.example.com".to_string()];
+        config.cors.allow_credentials = true;
+        let repository = HashMapRepository::new();
+        let app = create_app(
+            repository.clone().into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &config,
+        );
+        let req = Request::builder()
+            .uri("/todos/1")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization,content-type",
+            )
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let headers = res.headers();
+        assert_eq!(
+            "https://app.example.com",
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]
+        );
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap()
+            .contains("PATCH"));
+        assert_eq!(
+            "authorization,content-type",
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+        );
+        assert_eq!("true", headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS]);
+        assert_eq!("600", headers[header::ACCESS_CONTROL_MAX_AGE]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn close_todo_events_stream() -> http::Result<()> {
+        let events = EventBus::default();
+        let repository = HashMapRepository::new();
+        let req = Request::builder()
+            .uri("/todos/events")
+            .body(Body::empty())?;
+        let res = create_app(
+            repository.clone().into(),
+            repository.into(),
+            events.clone(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &test_config(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        events.close();
+        let mut body = res.into_body();
+        assert!(body.data().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn report_liveness() -> http::Result<()> {
+        let req = Request::builder().uri("/healthz").body(Body::empty())?;
+        let res = test_app(HashMapRepository::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body = response_to_result::<serde_json::Value>(res).await;
+        assert_eq!(json!({ "status": "up" }), body);
+        Ok(())
+    }
+
+    struct UnreachableCheck;
+
+    #[axum::async_trait]
+    impl health::HealthCheck for UnreachableCheck {
+        fn name(&self) -> &'static str {
+            "database"
+        }
+
+        async fn check(&self) -> anyhow::Result<serde_json::Value> {
+            Err(anyhow::anyhow!("connection refused"))
+        }
+    }
+
+    #[tokio::test]
+    async fn not_ready_while_dependency_is_down() -> http::Result<()> {
+        let repository = HashMapRepository::new();
+        let req = Request::builder().uri("/readyz").body(Body::empty())?;
+        let res = create_app(
+            repository.clone().into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::new(vec![Arc::new(UnreachableCheck)]),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &test_config(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        let body = response_to_result::<serde_json::Value>(res).await;
+        assert_eq!("down", body["status"]);
+        assert_eq!("down", body["checks"]["database"]["status"]);
+        assert_eq!("connection refused", body["checks"]["database"]["error"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ready_without_checks() -> http::Result<()> {
+        let req = Request::builder().uri("/readyz").body(Body::empty())?;
+        let res = test_app(HashMapRepository::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expose_metrics() -> http::Result<()> {
+        let metrics = Metrics::default();
+        let repository = HashMapRepository::new();
+        let app = create_app(
+            InstrumentedRepository::new(repository.clone(), metrics.clone()).into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::default(),
+            metrics,
+            &TaskTracker::new(),
+            &test_config(),
+        );
+        let req =
+            build_request_with_json("/todos", Method::POST, r#"{"text": "todo"}"#.to_string())?;
+        app.clone().oneshot(req).await.unwrap();
+        let req = Request::builder().uri("/todos/2").body(Body::empty())?;
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = Request::builder().uri("/metrics").body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(
+            body.contains(r#"http_requests_total{method="POST",route="/todos",status="201"} 1"#)
+        );
+        assert!(
+            body.contains(r#"http_requests_total{method="GET",route="/todos/:id",status="404"} 1"#)
+        );
+        assert!(body.contains(
+            r#"repository_operation_duration_seconds_count{operation="find",outcome="error"} 1"#
+        ));
+        assert!(body.contains("todos_total 1"));
+        assert!(body.contains("todos_open 1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn propagate_request_id() -> http::Result<()> {
+        let req = Request::builder()
+            .uri("/healthz")
+            .header(telemetry::REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())?;
+        let res = test_app(HashMapRepository::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!("abc-123", res.headers()[telemetry::REQUEST_ID_HEADER]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn assign_request_id() -> http::Result<()> {
+        let app = test_app(HashMapRepository::new());
+        let req = Request::builder().uri("/todos/1").body(Body::empty())?;
+        let first = app.clone().oneshot(req).await.unwrap();
+        let req = Request::builder().uri("/todos/1").body(Body::empty())?;
+        let second = app.oneshot(req).await.unwrap();
+        let first = &first.headers()[telemetry::REQUEST_ID_HEADER];
+        let second = &second.headers()[telemetry::REQUEST_ID_HEADER];
+        assert!(!first.is_empty());
+        assert_ne!(first, second);
+        Ok(())
+    }
+
+    async fn rate_limited_app(burst: u32) -> Router {
+        let mut config = test_config();
+        let budget = config::Budget {
+            burst,
+            per_minute: 1,
+        };
+        config.rate_limit.writes = budget;
+        config.rate_limit.login = budget;
+        let repository = HashMapRepository::new();
+        create_users(&repository, 1).await;
+        create_app(
+            repository.clone().into(),
+            repository.into(),
+            EventBus::default(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            &config,
+        )
+    }
+
+    #[tokio::test]
+    async fn limit_writes_per_user() -> http::Result<()> {
+        let app = rate_limited_app(2).await;
+        let create = |user_id| {
+            build_authorized_request_with_json(
+                "/todos",
+                Method::POST,
+                r#"{"text": "todo"}"#.to_string(),
+                user_id,
+            )
+        };
+        let res = app.clone().oneshot(create(1)?).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("2", res.headers()["ratelimit-limit"]);
+        assert_eq!("1", res.headers()["ratelimit-remaining"]);
+        app.clone().oneshot(create(1)?).await.unwrap();
+
+        let res = app.clone().oneshot(create(1)?).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        assert_eq!("60", res.headers()[header::RETRY_AFTER]);
+        assert_eq!("0", res.headers()["ratelimit-remaining"]);
+        assert_eq!("120", res.headers()["ratelimit-reset"]);
+
+        let res = app.oneshot(create(2)?).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn limit_failed_authentication() -> http::Result<()> {
+        let app = rate_limited_app(2).await;
+        let attempt = || {
+            Request::builder()
+                .uri("/webhooks")
+                .header(header::AUTHORIZATION, "Bearer guessed")
+                .body(Body::empty())
+        };
+        for _ in 0..2 {
+            let res = app.clone().oneshot(attempt()?).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        }
+        let res = app.clone().oneshot(attempt()?).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+
+        // a valid token is not an authentication attempt
+        let req = Request::builder()
+            .uri("/webhooks")
+            .header(header::AUTHORIZATION, bearer(1))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        Ok(())
+    }
+
+    /// Fails every call, either by panicking or by never answering.
+    #[derive(Clone)]
+    enum FaultyRepository {
+        Panic,
+        Stall,
+        Exhausted,
+    }
+
+    impl FaultyRepository {
+        async fn fail<T>(&self) -> anyhow::Result<T> {
+            match self {
+                FaultyRepository::Panic => panic!("repository exploded"),
+                FaultyRepository::Stall => std::future::pending().await,
+                FaultyRepository::Exhausted => Err(sqlx::Error::PoolTimedOut.into()),
+            }
+        }
+    }
+
+    #[axum::async_trait]
+    impl TodoRepository for FaultyRepository {
+        async fn create(&self, _: CreateTodo) -> anyhow::Result<Todo> {
+            self.fail().await
+        }
+
+        async fn find(&self, _: i32) -> anyhow::Result<Todo> {
+            self.fail().await
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+            self.fail().await
+        }
+
+        async fn update(&self, _: i32, _: repositories::todos::UpdateTodo) -> anyhow::Result<Todo> {
+            self.fail().await
+        }
+
+        async fn delete(&self, _: i32) -> anyhow::Result<()> {
+            self.fail().await
+        }
+
+        async fn stats(&self) -> anyhow::Result<repositories::todos::TodoStats> {
+            self.fail().await
+        }
+
+        async fn last_modified(&self) -> anyhow::Result<Option<chrono::DateTime<chrono::Utc>>> {
+            self.fail().await
+        }
+    }
+
+    fn faulty_app(repository: FaultyRepository, config: &Config) -> Router {
+        create_app(
+            repository.into(),
+            HashMapRepository::new().into(),
+            EventBus::default(),
+            Readiness::default(),
+            Metrics::default(),
+            &TaskTracker::new(),
+            config,
+        )
+    }
+
+    #[tokio::test]
+    async fn convert_panic_to_internal_server_error() -> http::Result<()> {
+        let app = faulty_app(FaultyRepository::Panic, &test_config());
+        let req = Request::builder().uri("/todos").body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        let bytes = to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&b"Internal server error"[..], &bytes[..]);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_out_slow_request() -> http::Result<()> {
+        let app = faulty_app(FaultyRepository::Stall, &test_config());
+        let req = Request::builder().uri("/todos").body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::REQUEST_TIMEOUT, res.status());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answer_503_when_pool_is_exhausted() -> http::Result<()> {
+        let app = faulty_app(FaultyRepository::Exhausted, &test_config());
+        for (method, uri) in [(Method::GET, "/todos"), (Method::DELETE, "/todos/1")] {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        }
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shed_requests_over_concurrency_limit() -> http::Result<()> {
+        let mut config = test_config();
+        config.server.max_concurrent_requests = 1;
+        let app = faulty_app(FaultyRepository::Stall, &config);
+        let req = Request::builder().uri("/todos").body(Body::empty())?;
+        let stalled = tokio::spawn(app.clone().oneshot(req));
+        // lets the stalled request take the only slot
+        tokio::time::sleep(Duration::from_millis(1)).await;
+
+        let req = Request::builder().uri("/todos").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        // the probes still answer, so that the busy instance is not restarted
+        for uri in ["/healthz", "/readyz"] {
+            let req = Request::builder().uri(uri).body(Body::empty())?;
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", uri);
+        }
+        // admitted, but it counts the todos of the stalled repository
+        let req = Request::builder().uri("/metrics").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::REQUEST_TIMEOUT, res.status());
+
+        let res = stalled.await.unwrap().unwrap();
+        assert_eq!(StatusCode::REQUEST_TIMEOUT, res.status());
+        let req = Request::builder().uri("/todos").body(Body::empty())?;
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::REQUEST_TIMEOUT, res.status());
+        Ok(())
+    }
 }
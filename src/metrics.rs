@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, State};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use sqlx::{Database, Pool};
+
+use crate::repositories::todos::TodoStats;
+
+/// Label for requests that did not match any route, so that probing random paths cannot
+/// blow up the number of series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// The metrics exposed on `/metrics`, kept in a registry of their own.
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    repository_operation_duration: HistogramVec,
+    cache_lookups: IntCounterVec,
+    todos_total: IntGauge,
+    todos_open: IntGauge,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time until the response head was ready",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let repository_operation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "repository_operation_duration_seconds",
+                "Latency of TodoRepository methods",
+            ),
+            &["operation", "outcome"],
+        )
+        .unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("todo_cache_lookups_total", "Lookups in the todo cache"),
+            &["operation", "result"],
+        )
+        .unwrap();
+        let todos_total = IntGauge::new("todos_total", "Todos stored").unwrap();
+        let todos_open = IntGauge::new("todos_open", "Todos not completed yet").unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(repository_operation_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_lookups.clone())).unwrap();
+        registry.register(Box::new(todos_total.clone())).unwrap();
+        registry.register(Box::new(todos_open.clone())).unwrap();
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            repository_operation_duration,
+            cache_lookups,
+            todos_total,
+            todos_open,
+        }
+    }
+
+    /// Reports the connections of `pool` on every scrape.
+    pub(crate) fn register_pool<DB: Database>(&self, pool: Pool<DB>) -> prometheus::Result<()> {
+        self.registry.register(Box::new(PoolCollector::new(pool)?))
+    }
+
+    /// Awaits a repository operation and records how long it took.
+    pub(crate) async fn observe_repository<T>(
+        &self,
+        operation: &str,
+        future: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let started = Instant::now();
+        let result = future.await;
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.repository_operation_duration
+            .with_label_values(&[operation, outcome])
+            .observe(started.elapsed().as_secs_f64());
+        result
+    }
+
+    /// Counts a lookup in the todo cache by the repository method it stands in for.
+    pub(crate) fn record_cache_lookup(&self, operation: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.cache_lookups
+            .with_label_values(&[operation, result])
+            .inc();
+    }
+
+    pub(crate) fn set_todo_stats(&self, stats: TodoStats) {
+        self.todos_total.set(stats.total);
+        self.todos_open.set(stats.open);
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub(crate) fn encode(&self) -> prometheus::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        // the text encoder only ever writes UTF-8
+        Ok(String::from_utf8(buffer).unwrap())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware counting requests and their latency by method, matched route and status.
+pub(crate) async fn track_requests<B>(
+    State(metrics): State<Metrics>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+    let method = req.method().clone();
+    let started = Instant::now();
+    let res = next.run(req).await;
+    let status = res.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    metrics.http_requests.with_label_values(&labels).inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+    res
+}
+
+/// Samples the size of a pool whenever the registry is gathered.
+struct PoolCollector<DB: Database> {
+    pool: Pool<DB>,
+    connections: IntGauge,
+    idle_connections: IntGauge,
+}
+
+impl<DB: Database> PoolCollector<DB> {
+    fn new(pool: Pool<DB>) -> prometheus::Result<Self> {
+        Ok(Self {
+            pool,
+            connections: IntGauge::new(
+                "db_pool_connections",
+                "Connections currently open in the pool",
+            )?,
+            idle_connections: IntGauge::new(
+                "db_pool_idle_connections",
+                "Open connections not in use",
+            )?,
+        })
+    }
+}
+
+impl<DB: Database> Collector for PoolCollector<DB> {
+    fn desc(&self) -> Vec<&Desc> {
+        [&self.connections, &self.idle_connections]
+            .into_iter()
+            .flat_map(|gauge| gauge.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.connections.set(self.pool.size().into());
+        self.idle_connections.set(self.pool.num_idle() as i64);
+        [&self.connections, &self.idle_connections]
+            .into_iter()
+            .flat_map(|gauge| gauge.collect())
+            .collect()
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::pool::PoolConnection;
+use sqlx::{PgConnection, PgPool, Postgres};
+
+/// Versions of the migrations applied to the database, empty when it was never migrated.
+pub(crate) async fn applied(conn: &mut PgConnection) -> anyhow::Result<HashSet<i64>> {
+    // a database that was never migrated has no bookkeeping table yet
+    let bookkept =
+        sqlx::query_scalar!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "exists!""#)
+            .fetch_one(&mut *conn)
+            .await?;
+    if !bookkept {
+        return Ok(HashSet::new());
+    }
+    if let Some(version) = conn.dirty_version().await? {
+        anyhow::bail!("migration {} failed and left the database dirty", version);
+    }
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
+
+/// Versions `migrator` ships that are not applied yet.
+pub(crate) fn pending(migrator: &Migrator, applied: &HashSet<i64>) -> Vec<i64> {
+    migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect()
+}
+
+/// Fails when the database was migrated by a newer build, whose schema this one may not
+/// be able to work with.
+pub(crate) async fn ensure_known(pool: &PgPool, migrator: &Migrator) -> anyhow::Result<()> {
+    let mut conn = pool.acquire().await?;
+    check_known(&mut conn, migrator).await
+}
+
+async fn check_known(conn: &mut PgConnection, migrator: &Migrator) -> anyhow::Result<()> {
+    let known: HashSet<i64> = migrator.iter().map(|migration| migration.version).collect();
+    let mut unknown: Vec<i64> = applied(conn).await?.difference(&known).copied().collect();
+    unknown.sort_unstable();
+    if !unknown.is_empty() {
+        anyhow::bail!(
+            "the database has migrations {:?} applied that this build does not know about, \
+             it was migrated by a newer version",
+            unknown
+        );
+    }
+    Ok(())
+}
+
+/// Where a migration stands in the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum State {
+    Applied,
+    Pending,
+    /// Applied by a newer build.
+    Unknown,
+}
+
+/// Every migration this build ships or the database has applied, by version.
+pub(crate) async fn status(
+    pool: &PgPool,
+    migrator: &Migrator,
+) -> anyhow::Result<Vec<(i64, String, State)>> {
+    let mut conn = pool.acquire().await?;
+    let applied = applied(&mut conn).await?;
+    let mut status: Vec<_> = migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let state = if applied.contains(&migration.version) {
+                State::Applied
+            } else {
+                State::Pending
+            };
+            (migration.version, migration.description.to_string(), state)
+        })
+        .collect();
+    for version in applied {
+        if !status.iter().any(|(known, ..)| *known == version) {
+            status.push((version, String::new(), State::Unknown));
+        }
+    }
+    status.sort_by_key(|(version, ..)| *version);
+    Ok(status)
+}
+
+/// Applies the pending migrations.
+///
+/// The whole run holds a Postgres advisory lock, so replicas starting at the same time
+/// apply each migration once and wait for each other.
+pub(crate) async fn run(pool: &PgPool, migrator: &Migrator) -> anyhow::Result<()> {
+    let mut conn = lock(pool).await?;
+    let result = async {
+        check_known(&mut conn, migrator).await?;
+        let pending = pending(migrator, &applied(&mut conn).await?);
+        if pending.is_empty() {
+            return anyhow::Ok(());
+        }
+        tracing::info!("applying migrations {:?}", pending);
+        // the migrator takes the same lock again, which Postgres allows within a session
+        migrator.run(&mut *conn).await?;
+        Ok(())
+    }
+    .await;
+    unlock(conn, result).await
+}
+
+/// Reverts the migrations applied after `target`, or only the latest one without a target,
+/// and returns their versions.
+///
+/// Fails without reverting anything when one of them has no down script.
+pub(crate) async fn undo(
+    pool: &PgPool,
+    migrator: &Migrator,
+    target: Option<i64>,
+) -> anyhow::Result<Vec<i64>> {
+    let mut conn = lock(pool).await?;
+    let result = async {
+        check_known(&mut conn, migrator).await?;
+        let mut applied: Vec<i64> = applied(&mut conn).await?.into_iter().collect();
+        applied.sort_unstable_by(|a, b| b.cmp(a));
+        let target = match target {
+            Some(target) => target,
+            None => applied.get(1).copied().unwrap_or(0),
+        };
+        let reverted: Vec<i64> = applied
+            .into_iter()
+            .filter(|version| *version > target)
+            .collect();
+        for version in &reverted {
+            let reversible = migrator.iter().any(|migration| {
+                migration.version == *version && migration.migration_type.is_down_migration()
+            });
+            if !reversible {
+                anyhow::bail!(
+                    "migration {} has no down script and cannot be reverted",
+                    version
+                );
+            }
+        }
+        if !reverted.is_empty() {
+            tracing::info!("reverting migrations {:?}", reverted);
+            migrator.undo(&mut *conn, target).await?;
+        }
+        Ok(reverted)
+    }
+    .await;
+    unlock(conn, result).await
+}
+
+async fn lock(pool: &PgPool) -> anyhow::Result<PoolConnection<Postgres>> {
+    let mut conn = pool.acquire().await?;
+    // waiting for the lock and applying a migration may take longer than a query may
+    let locked = async {
+        sqlx::query("SET statement_timeout = 0")
+            .execute(&mut *conn)
+            .await?;
+        conn.lock().await
+    }
+    .await;
+    if let Err(e) = locked {
+        drop(conn.detach());
+        return Err(e).context("failed to take the migration lock");
+    }
+    Ok(conn)
+}
+
+async fn unlock<T>(
+    mut conn: PoolConnection<Postgres>,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let unlocked = async {
+        conn.unlock().await?;
+        sqlx::query("RESET statement_timeout")
+            .execute(&mut *conn)
+            .await
+    }
+    .await;
+    if let Err(e) = unlocked {
+        // the lock and the lifted timeout live as long as the session, so the connection must
+        // not go back to the pool
+        drop(conn.detach());
+        result?;
+        return Err(e).context("failed to release the migration lock");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use sqlx::migrate::{Migration, MigrationType};
+
+    use super::*;
+
+    fn migration(version: i64, migration_type: MigrationType) -> Migration {
+        Migration::new(
+            version,
+            Cow::Borrowed("test"),
+            migration_type,
+            Cow::Borrowed("SELECT 1"),
+        )
+    }
+
+    #[test]
+    fn list_pending_migrations() {
+        let migrator = Migrator {
+            migrations: Cow::Owned(vec![
+                migration(1, MigrationType::ReversibleUp),
+                migration(1, MigrationType::ReversibleDown),
+                migration(2, MigrationType::ReversibleUp),
+                migration(3, MigrationType::ReversibleUp),
+                migration(3, MigrationType::ReversibleDown),
+            ]),
+            ignore_missing: false,
+            locking: true,
+        };
+        assert_eq!(vec![2, 3], pending(&migrator, &HashSet::from([1])));
+        assert!(pending(&migrator, &HashSet::from([1, 2, 3])).is_empty());
+    }
+}
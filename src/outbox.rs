@@ -0,0 +1,231 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio_util::sync::CancellationToken;
+
+use crate::events::{TodoEvent, TODO_EVENTS_CHANNEL};
+
+const BATCH_SIZE: i64 = 100;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETENTION_DAYS: i32 = 7;
+
+/// Destination the relay hands every outbox event to.
+#[async_trait]
+pub(crate) trait OutboxSink: Send + Sync {
+    /// Takes on `event` within the relay's transaction on `conn`, which marks it delivered.
+    async fn deliver(&self, conn: &mut PgConnection, event: &TodoEvent) -> anyhow::Result<()>;
+}
+
+/// Records `event` in the outbox as part of the caller's transaction.
+pub(crate) async fn enqueue(conn: &mut PgConnection, event: &TodoEvent) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO outbox (payload) VALUES ($1)
+        "#,
+        serde_json::to_string(event)?,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Re-publishes outbox events on `TODO_EVENTS_CHANNEL` for the listener of every instance,
+/// once the relay's transaction commits.
+pub(crate) struct NotifySink;
+
+#[async_trait]
+impl OutboxSink for NotifySink {
+    async fn deliver(&self, conn: &mut PgConnection, event: &TodoEvent) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            SELECT pg_notify($1, $2)
+            "#,
+            TODO_EVENTS_CHANNEL,
+            serde_json::to_string(event)?,
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Drains the outbox into the sinks in insertion order.
+///
+/// The sinks work in the transaction that marks a row delivered, each row under a savepoint,
+/// so an event takes effect in every sink exactly once: a failing sink undoes what the others
+/// did with the row, and the row is retried on the next poll. Rows are locked with
+/// `SKIP LOCKED`, which lets several instances run a relay without delivering twice.
+/// A row whose payload cannot be read is marked delivered with the reason in `error`, and kept
+/// by the purge for someone to look into.
+pub(crate) struct OutboxRelay {
+    pool: PgPool,
+    sinks: Vec<Arc<dyn OutboxSink>>,
+}
+
+impl OutboxRelay {
+    pub(crate) fn new(pool: PgPool, sinks: Vec<Arc<dyn OutboxSink>>) -> Self {
+        Self { pool, sinks }
+    }
+
+    /// Polls the outbox until `shutdown` is cancelled, finishing the batch in progress.
+    pub(crate) async fn run(self, shutdown: CancellationToken) {
+        let mut last_purge = Instant::now();
+        while !shutdown.is_cancelled() {
+            match self.drain().await {
+                Ok(count) if count as i64 == BATCH_SIZE => continue,
+                Ok(_) => {}
+                Err(e) => tracing::error!("failed to relay outbox events: {}", e),
+            }
+            if last_purge.elapsed() >= PURGE_INTERVAL {
+                match self.purge().await {
+                    Ok(count) => tracing::debug!("purged {} delivered outbox events", count),
+                    Err(e) => tracing::error!("failed to purge outbox events: {}", e),
+                }
+                last_purge = Instant::now();
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Delivers one batch of pending events and returns how many were marked delivered or set
+    /// aside.
+    async fn drain(&self) -> anyhow::Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, payload FROM outbox
+            WHERE delivered_at IS NULL
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            BATCH_SIZE,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut delivered = 0;
+        for row in rows {
+            let event: TodoEvent = match serde_json::from_str(&row.payload) {
+                Ok(event) => event,
+                Err(e) => {
+                    // retrying would not help, and would hold up every later event
+                    tracing::error!("set aside unreadable outbox event {}: {}", row.id, e);
+                    sqlx::query!(
+                        r#"
+                        UPDATE outbox SET delivered_at = now(), error = $2
+                        WHERE id = $1
+                        "#,
+                        row.id,
+                        e.to_string(),
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    delivered += 1;
+                    continue;
+                }
+            };
+            let mut savepoint = tx.begin().await?;
+            if let Err(e) = self.deliver(&mut savepoint, &event).await {
+                // keep the order: this and later events are retried on the next poll
+                tracing::warn!("failed to deliver outbox event {}: {}", row.id, e);
+                savepoint.rollback().await?;
+                break;
+            }
+            savepoint.commit().await?;
+            sqlx::query!(
+                r#"
+                UPDATE outbox SET delivered_at = now()
+                WHERE id = $1
+                "#,
+                row.id,
+            )
+            .execute(&mut *tx)
+            .await?;
+            delivered += 1;
+        }
+        tx.commit().await?;
+        Ok(delivered)
+    }
+
+    async fn deliver(&self, conn: &mut PgConnection, event: &TodoEvent) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            sink.deliver(conn, event).await?;
+        }
+        Ok(())
+    }
+
+    async fn purge(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM outbox
+            WHERE delivered_at < now() - make_interval(days => $1) AND error IS NULL
+            "#,
+            RETENTION_DAYS,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::events::TodoEventKind;
+    use crate::repositories::postgres::test_utils;
+    use crate::repositories::todos::Todo;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<TodoEvent>>,
+    }
+
+    #[async_trait]
+    impl OutboxSink for RecordingSink {
+        async fn deliver(&self, _: &mut PgConnection, event: &TodoEvent) -> anyhow::Result<()> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+    async fn set_aside_unreadable_event() {
+        let pool = test_utils::database().await;
+        sqlx::query("INSERT INTO outbox (payload) VALUES ('not an event')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let event = TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: Todo::new(1, "todo".to_string()),
+        };
+        enqueue(&mut pool.acquire().await.unwrap(), &event)
+            .await
+            .unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let relay = OutboxRelay::new(pool.clone(), vec![sink.clone()]);
+
+        assert_eq!(2, relay.drain().await.unwrap());
+        assert_eq!(vec![event], *sink.events.lock().unwrap());
+        let errors: Vec<Option<String>> = sqlx::query_scalar(
+            "SELECT error FROM outbox WHERE delivered_at IS NOT NULL ORDER BY id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert!(errors[0].is_some());
+        assert_eq!(None, errors[1]);
+
+        test_utils::drop_database(pool).await;
+    }
+}
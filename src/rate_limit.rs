@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::config::{Budget, RateLimitConfig};
+use crate::handlers::auth::{bearer_token, JwtKeys};
+
+/// Buckets that are full again carry no state and are dropped once the store grows this big.
+const PRUNE_THRESHOLD: usize = 10_000;
+/// How long after a prune the store may grow past the threshold before it prunes again, so
+/// that a store full of busy buckets is not scanned on every request.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Outcome of asking a bucket for a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Decision {
+    pub(crate) allowed: bool,
+    pub(crate) limit: u32,
+    pub(crate) remaining: u32,
+    /// Until the bucket is full again.
+    pub(crate) reset: Duration,
+    /// Until the next token is available.
+    pub(crate) retry_after: Duration,
+}
+
+/// Keeps the token buckets; an implementation backed by a shared store lets several
+/// instances enforce one budget.
+#[async_trait]
+pub(crate) trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket `key` if there is one.
+    async fn take(&self, key: &str, budget: Budget) -> Decision;
+    /// Reports whether `take` would succeed without taking anything.
+    async fn peek(&self, key: &str, budget: Budget) -> Decision;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// What the bucket was last taken from with, for pruning it alongside other classes.
+    budget: Budget,
+}
+
+impl Bucket {
+    fn full(budget: Budget, now: Instant) -> Self {
+        Self {
+            tokens: budget.burst.into(),
+            updated: now,
+            budget,
+        }
+    }
+
+    fn refill(&mut self, budget: Budget, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate(budget)).min(budget.burst.into());
+        self.updated = now;
+    }
+
+    fn decision(&self, budget: Budget, allowed: bool) -> Decision {
+        let missing = |tokens: f64| Duration::from_secs_f64(tokens.max(0.0) / rate(budget));
+        Decision {
+            allowed,
+            limit: budget.burst,
+            remaining: self.tokens.floor() as u32,
+            reset: missing(f64::from(budget.burst) - self.tokens),
+            retry_after: missing(1.0 - self.tokens),
+        }
+    }
+}
+
+/// Tokens per second.
+fn rate(budget: Budget) -> f64 {
+    f64::from(budget.per_minute) / 60.0
+}
+
+/// Buckets kept in this process.
+#[derive(Debug, Default)]
+pub(crate) struct InMemoryStore {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    pruned: Option<Instant>,
+}
+
+impl InMemoryStore {
+    fn take_at(&self, key: &str, budget: Budget, now: Instant) -> Decision {
+        let mut buckets = self.buckets.lock().unwrap();
+        let due = buckets
+            .pruned
+            .is_none_or(|pruned| now.saturating_duration_since(pruned) >= PRUNE_INTERVAL);
+        if buckets.by_key.len() >= PRUNE_THRESHOLD && due {
+            buckets.by_key.retain(|_, bucket| {
+                let budget = bucket.budget;
+                bucket.refill(budget, now);
+                bucket.tokens < budget.burst.into()
+            });
+            buckets.pruned = Some(now);
+        }
+        let bucket = buckets
+            .by_key
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::full(budget, now));
+        bucket.budget = budget;
+        bucket.refill(budget, now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        bucket.decision(budget, allowed)
+    }
+
+    fn peek_at(&self, key: &str, budget: Budget, now: Instant) -> Decision {
+        let mut bucket = self
+            .buckets
+            .lock()
+            .unwrap()
+            .by_key
+            .get(key)
+            .copied()
+            .unwrap_or_else(|| Bucket::full(budget, now));
+        bucket.refill(budget, now);
+        bucket.decision(budget, bucket.tokens >= 1.0)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn take(&self, key: &str, budget: Budget) -> Decision {
+        self.take_at(key, budget, Instant::now())
+    }
+
+    async fn peek(&self, key: &str, budget: Budget) -> Decision {
+        self.peek_at(key, budget, Instant::now())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    trusted_proxies: Arc<[IpAddr]>,
+    store: Arc<dyn RateLimitStore>,
+    keys: Arc<JwtKeys>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(
+        config: RateLimitConfig,
+        store: Arc<dyn RateLimitStore>,
+        keys: Arc<JwtKeys>,
+    ) -> Self {
+        // validated with the rest of the config
+        let trusted_proxies = config
+            .trusted_proxies
+            .iter()
+            .filter_map(|proxy| proxy.parse().ok())
+            .collect();
+        Self {
+            config,
+            trusted_proxies,
+            store,
+            keys,
+        }
+    }
+}
+
+/// The address the request came from: the peer, unless that is a trusted proxy, in which case
+/// the proxies' headers are followed back to the first address that is not one.
+fn client_ip<B>(req: &Request<B>, trusted_proxies: &[IpAddr]) -> String {
+    let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>() else {
+        return "unknown".to_string();
+    };
+    let mut ip = peer.ip();
+    if trusted_proxies.contains(&ip) {
+        for forwarded in forwarded_for(req.headers()).into_iter().rev() {
+            // an address that is hidden or malformed cannot be followed any further
+            let Some(forwarded) = forwarded else { break };
+            ip = forwarded;
+            if !trusted_proxies.contains(&ip) {
+                break;
+            }
+        }
+    }
+    ip.to_string()
+}
+
+/// The addresses the proxies forwarded for, nearest last, from the `Forwarded` header or else
+/// `X-Forwarded-For`; `None` for those that are not IP addresses.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(str::trim)
+    };
+    if headers.contains_key(header::FORWARDED) {
+        values(header::FORWARDED)
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim_matches('"')))
+            })
+            .collect()
+    } else {
+        values(header::HeaderName::from_static("x-forwarded-for"))
+            .map(parse_node)
+            .collect()
+    }
+}
+
+/// An IP address, optionally with a port and IPv6 addresses in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+/// Middleware enforcing the read and write budgets per user or client IP, and the login
+/// budget on failed authentication attempts per client IP.
+pub(crate) async fn limit_requests<B>(
+    State(limiter): State<RateLimiter>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !limiter.config.enabled {
+        return next.run(req).await;
+    }
+    let config = &limiter.config;
+    let store = &limiter.store;
+
+    let ip = client_ip(&req, &limiter.trusted_proxies);
+    let user = bearer_token(req.headers()).map(|token| limiter.keys.verify(token).ok());
+    // a client guessing tokens is cut off before its next attempt is even verified
+    let login = matches!(user, Some(None)).then(|| format!("login:ip:{}", ip));
+    if let Some(key) = &login {
+        let decision = store.peek(key, config.login).await;
+        if !decision.allowed {
+            return too_many_requests(decision);
+        }
+    }
+
+    let (class, budget) = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => ("reads", config.reads),
+        _ => ("writes", config.writes),
+    };
+    let key = match user.flatten() {
+        Some(user_id) => format!("{}:user:{}", class, user_id),
+        None => format!("{}:ip:{}", class, ip),
+    };
+    let decision = store.take(&key, budget).await;
+    if !decision.allowed {
+        return too_many_requests(decision);
+    }
+
+    let mut res = next.run(req).await;
+    if let (Some(key), StatusCode::UNAUTHORIZED) = (&login, res.status()) {
+        store.take(key, config.login).await;
+    }
+    set_headers(res.headers_mut(), decision);
+    res
+}
+
+fn too_many_requests(decision: Decision) -> Response {
+    let mut res = (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+    set_headers(res.headers_mut(), decision);
+    res.headers_mut().insert(
+        "retry-after",
+        HeaderValue::from(seconds(decision.retry_after)),
+    );
+    res
+}
+
+/// Advertises the budget as proposed by the IETF `RateLimit` header fields draft.
+fn set_headers(headers: &mut HeaderMap, decision: Decision) {
+    headers.insert("ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert(
+        "ratelimit-reset",
+        HeaderValue::from(seconds(decision.reset)),
+    );
+}
+
+/// Whole seconds, rounded up so that a client waiting that long is not turned away again.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Budget = Budget {
+        burst: 2,
+        per_minute: 60,
+    };
+
+    #[test]
+    fn refill_one_token_per_period() {
+        let store = InMemoryStore::default();
+        let start = Instant::now();
+        assert!(store.take_at("key", BUDGET, start).allowed);
+        let decision = store.take_at("key", BUDGET, start);
+        assert!(decision.allowed);
+        assert_eq!(0, decision.remaining);
+        assert_eq!(Duration::from_secs(2), decision.reset);
+
+        let decision = store.take_at("key", BUDGET, start);
+        assert!(!decision.allowed);
+        assert_eq!(Duration::from_secs(1), decision.retry_after);
+
+        let later = start + Duration::from_secs(1);
+        assert!(store.take_at("key", BUDGET, later).allowed);
+        assert!(!store.take_at("key", BUDGET, later).allowed);
+    }
+
+    #[test]
+    fn keep_buckets_apart() {
+        let store = InMemoryStore::default();
+        let now = Instant::now();
+        store.take_at("a", BUDGET, now);
+        store.take_at("a", BUDGET, now);
+        assert!(!store.take_at("a", BUDGET, now).allowed);
+        assert!(store.take_at("b", BUDGET, now).allowed);
+    }
+
+    #[test]
+    fn prune_each_bucket_by_its_own_budget() {
+        const GENEROUS: Budget = Budget {
+            burst: 100,
+            per_minute: 6000,
+        };
+        const SLOW: Budget = Budget {
+            burst: 2,
+            per_minute: 1,
+        };
+        let store = InMemoryStore::default();
+        let start = Instant::now();
+        store.take_at("writes", SLOW, start);
+        for i in 0..PRUNE_THRESHOLD {
+            store.take_at(&format!("reads:{}", i), GENEROUS, start);
+        }
+
+        // prunes the reads, which are full again, but keeps the writes, which are not
+        let later = start + PRUNE_INTERVAL;
+        store.take_at("reads", GENEROUS, later);
+        assert_eq!(2, store.buckets.lock().unwrap().by_key.len());
+        assert!(store.take_at("writes", SLOW, later).allowed);
+        assert!(!store.take_at("writes", SLOW, later).allowed);
+    }
+
+    #[test]
+    fn prune_at_most_once_per_interval() {
+        let store = InMemoryStore::default();
+        let start = Instant::now();
+        for i in 0..=PRUNE_THRESHOLD {
+            store.take_at(&format!("key:{}", i), BUDGET, start);
+        }
+        // the prune when the threshold was reached found every bucket in use
+        assert_eq!(Some(start), store.buckets.lock().unwrap().pruned);
+
+        // the buckets are full again, but the store does not scan before the interval passes
+        let soon = start + Duration::from_secs(2);
+        store.take_at("other", BUDGET, soon);
+        assert_eq!(
+            PRUNE_THRESHOLD + 2,
+            store.buckets.lock().unwrap().by_key.len()
+        );
+
+        store.take_at("other", BUDGET, start + PRUNE_INTERVAL);
+        assert_eq!(1, store.buckets.lock().unwrap().by_key.len());
+    }
+
+    fn request(peer: [u8; 4], headers: &[(&'static str, &str)]) -> Request<()> {
+        let mut req = Request::new(());
+        for (name, value) in headers {
+            req.headers_mut()
+                .append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((peer, 443))));
+        req
+    }
+
+    #[test]
+    fn ignore_forwarded_headers_of_untrusted_peers() {
+        let proxies = ["10.0.0.1".parse().unwrap()];
+        let req = request([192, 0, 2, 1], &[("x-forwarded-for", "198.51.100.7")]);
+        assert_eq!("192.0.2.1", client_ip(&req, &proxies));
+    }
+
+    #[test]
+    fn follow_trusted_proxies_back_to_the_client() {
+        let proxies = ["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        // the client may claim anything before its own address
+        let req = request(
+            [10, 0, 0, 1],
+            &[("x-forwarded-for", "203.0.113.9, 198.51.100.7, 10.0.0.2")],
+        );
+        assert_eq!("198.51.100.7", client_ip(&req, &proxies));
+
+        let req = request(
+            [10, 0, 0, 1],
+            &[
+                ("forwarded", r#"for=203.0.113.9, for="[2001:db8::1]:4711""#),
+                ("forwarded", "for=10.0.0.2;proto=https"),
+                ("x-forwarded-for", "198.51.100.7"),
+            ],
+        );
+        assert_eq!("2001:db8::1", client_ip(&req, &proxies));
+
+        let req = request([10, 0, 0, 1], &[("forwarded", "for=_hidden")]);
+        assert_eq!("10.0.0.1", client_ip(&req, &proxies));
+    }
+
+    #[test]
+    fn peek_without_taking() {
+        let store = InMemoryStore::default();
+        let now = Instant::now();
+        let decision = store.peek_at("key", BUDGET, now);
+        assert!(decision.allowed);
+        assert_eq!(2, decision.remaining);
+        assert_eq!(2, store.take_at("key", BUDGET, now).limit);
+        assert_eq!(1, store.peek_at("key", BUDGET, now).remaining);
+    }
+}
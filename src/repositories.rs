@@ -1,9 +1,26 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, RwLock};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use sqlx::pool::PoolConnection;
+use sqlx::{Database, Pool, Transaction};
 use thiserror::Error;
 
+use crate::repositories::todos::TodoRepository;
+use crate::repositories::users::UserRepository;
+
+#[cfg(test)]
+mod conformance;
+pub(crate) mod event_sourced;
+pub(crate) mod file;
 pub(crate) mod hash_map;
 pub(crate) mod postgres;
+pub(crate) mod sqlite;
 pub(crate) mod todos;
-mod users;
+pub(crate) mod users;
+pub(crate) mod webhooks;
 
 #[derive(Debug, Error)]
 enum RepositoryError<T> {
@@ -12,3 +29,117 @@ enum RepositoryError<T> {
     #[error("NotFound, {0}: {1}")]
     NotFound(String, T),
 }
+
+/// Repositories that can run several operations as one.
+#[async_trait]
+pub(crate) trait Transactional: TodoRepository + UserRepository {
+    /// Runs `work` on a repository whose changes are all kept once `work` succeeds and none
+    /// of them when it fails. Events are published once the changes are kept.
+    ///
+    /// `work` must only go through the repository it is given, and must not keep it. It
+    /// borrows nothing else, so that decorators can hand it on to the repository they wrap.
+    /// Within `work`, a further transaction is part of this one.
+    async fn transaction<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'r> FnOnce(&'r Self) -> BoxFuture<'r, anyhow::Result<T>> + Send + 'static;
+}
+
+/// The transaction the clones of a database repository run in, while one is.
+pub(crate) type SharedTransaction<DB> = Arc<tokio::sync::Mutex<Transaction<'static, DB>>>;
+
+pub(crate) async fn begin<DB: Database>(pool: &Pool<DB>) -> anyhow::Result<SharedTransaction<DB>> {
+    Ok(Arc::new(tokio::sync::Mutex::new(pool.begin().await?)))
+}
+
+/// Commits `transaction` when `result` is ok and rolls it back otherwise.
+pub(crate) async fn finish<DB: Database, T>(
+    transaction: SharedTransaction<DB>,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    // a clone of the repository kept past `work` would roll back once dropped
+    let transaction = Arc::try_unwrap(transaction)
+        .map_err(|_| anyhow::anyhow!("a repository outlived its transaction"))?
+        .into_inner();
+    match result {
+        Ok(value) => {
+            transaction.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            transaction.rollback().await?;
+            Err(e)
+        }
+    }
+}
+
+/// What a database repository runs its queries on: a connection of the pool, or the one of
+/// the transaction it runs in.
+pub(crate) enum DbConnection<'a, DB: Database> {
+    Pool(PoolConnection<DB>),
+    Transaction(tokio::sync::MutexGuard<'a, Transaction<'static, DB>>),
+}
+
+impl<'a, DB: Database> DbConnection<'a, DB> {
+    pub(crate) async fn acquire(
+        pool: &Pool<DB>,
+        transaction: &'a Option<SharedTransaction<DB>>,
+    ) -> anyhow::Result<Self> {
+        match transaction {
+            Some(transaction) => Ok(DbConnection::Transaction(transaction.lock().await)),
+            None => Ok(DbConnection::Pool(pool.acquire().await?)),
+        }
+    }
+}
+
+impl<DB: Database> Deref for DbConnection<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            DbConnection::Pool(connection) => connection,
+            DbConnection::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+impl<DB: Database> DerefMut for DbConnection<'_, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            DbConnection::Pool(connection) => connection,
+            DbConnection::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+/// Where repositories that keep their own timestamps take them from; tests stop it so that
+/// the timestamps are predictable.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Clock {
+    stopped: Option<Arc<RwLock<DateTime<Utc>>>>,
+}
+
+impl Clock {
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        match &self.stopped {
+            Some(now) => *now.read().unwrap(),
+            None => Utc::now(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn stopped_at(now: DateTime<Utc>) -> Self {
+        Self {
+            stopped: Some(Arc::new(RwLock::new(now))),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set(&self, now: DateTime<Utc>) {
+        let stopped = self
+            .stopped
+            .as_ref()
+            .expect("only a stopped clock can be set");
+        *stopped.write().unwrap() = now;
+    }
+}
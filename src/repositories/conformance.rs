@@ -0,0 +1,469 @@
+//! What every backend has to do alike, run against each of them.
+//!
+//! The Postgres tests are ignored by default, as they need a server: run them with
+//! `--include-ignored` and `TEST_DATABASE_URL` naming one the tests may create databases on,
+//! e.g. the one of `docker-compose.yml`. Every test gets a database of its own, dropped once
+//! the test passed.
+
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Display};
+use std::path::Path;
+
+use axum::async_trait;
+
+use crate::repositories::event_sourced::EventSourcedRepository;
+use crate::repositories::file::FileRepository;
+use crate::repositories::hash_map::HashMapRepository;
+use crate::repositories::postgres::{test_utils as postgres_utils, PostgresRepository};
+use crate::repositories::sqlite::SqliteRepository;
+use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, UpdateTodo};
+use crate::repositories::users::{CreateUser, UpdateUser, User, UserRepository};
+use crate::repositories::{RepositoryError, Transactional};
+
+#[async_trait]
+trait Fixture: TodoRepository {
+    /// An empty repository; backends kept in files keep them in `dir`, which is removed
+    /// after the test.
+    async fn open(dir: &Path) -> Self;
+
+    /// Cleans up after a test that passed.
+    async fn close(self) {}
+}
+
+#[async_trait]
+impl Fixture for HashMapRepository {
+    async fn open(_: &Path) -> Self {
+        HashMapRepository::new()
+    }
+}
+
+#[async_trait]
+impl Fixture for SqliteRepository {
+    async fn open(_: &Path) -> Self {
+        crate::repositories::sqlite::test_utils::in_memory().await
+    }
+}
+
+#[async_trait]
+impl Fixture for FileRepository {
+    async fn open(dir: &Path) -> Self {
+        crate::repositories::file::test_utils::open(dir, 1000).await
+    }
+}
+
+#[async_trait]
+impl Fixture for EventSourcedRepository {
+    async fn open(dir: &Path) -> Self {
+        crate::repositories::event_sourced::test_utils::open(dir, 1000).await
+    }
+}
+
+#[async_trait]
+impl Fixture for PostgresRepository {
+    async fn open(_: &Path) -> Self {
+        PostgresRepository::new(postgres_utils::database().await)
+    }
+
+    async fn close(self) {
+        postgres_utils::drop_database(self.pool).await;
+    }
+}
+
+fn assert_not_found<T, V>(result: anyhow::Result<T>)
+where
+    T: Debug,
+    V: Debug + Display + Send + Sync + 'static,
+{
+    let e = result.unwrap_err();
+    assert!(
+        matches!(
+            e.downcast_ref::<RepositoryError<V>>(),
+            Some(RepositoryError::NotFound(..))
+        ),
+        "expected NotFound, got: {:#}",
+        e
+    );
+}
+
+async fn create_todos<R: TodoRepository>(repository: &R, texts: &[&str]) -> Vec<Todo> {
+    let mut todos = Vec::new();
+    for text in texts {
+        let todo = TodoRepository::create(repository, CreateTodo::new(text.to_string()))
+            .await
+            .unwrap();
+        todos.push(todo);
+    }
+    todos
+}
+
+fn ids(todos: &[Todo]) -> Vec<i32> {
+    todos.iter().map(|todo| todo.id).collect()
+}
+
+fn create_user(email: &str) -> CreateUser {
+    CreateUser {
+        username: "user".to_string(),
+        email: email.to_string(),
+        password_hash: "hash".to_string(),
+    }
+}
+
+async fn create_and_find_todo<R: Fixture>(repository: R) {
+    let todo = TodoRepository::create(&repository, CreateTodo::new("text".to_string()))
+        .await
+        .unwrap();
+    assert_eq!("text", todo.text);
+    assert!(!todo.completed);
+
+    assert_eq!(
+        todo,
+        TodoRepository::find(&repository, todo.id).await.unwrap()
+    );
+}
+
+async fn update_only_given_todo_fields<R: Fixture>(repository: R) {
+    let [todo] = &create_todos(&repository, &["text"]).await[..] else {
+        unreachable!()
+    };
+    let completed = UpdateTodo {
+        text: None,
+        completed: Some(true),
+    };
+    let updated = TodoRepository::update(&repository, todo.id, completed)
+        .await
+        .unwrap();
+    assert_eq!(("text", true), (updated.text.as_str(), updated.completed));
+    let renamed = UpdateTodo {
+        text: Some("renamed".to_string()),
+        completed: None,
+    };
+    let updated = TodoRepository::update(&repository, todo.id, renamed)
+        .await
+        .unwrap();
+    assert_eq!(
+        ("renamed", true),
+        (updated.text.as_str(), updated.completed)
+    );
+
+    assert_eq!(
+        updated,
+        TodoRepository::find(&repository, todo.id).await.unwrap()
+    );
+}
+
+async fn list_newest_todo_first<R: Fixture>(repository: R) {
+    let todos = create_todos(&repository, &["first", "second", "third"]).await;
+    let mut expected = ids(&todos);
+    expected.reverse();
+
+    let all = TodoRepository::all(&repository).await.unwrap();
+    assert_eq!(expected, ids(&all));
+}
+
+async fn never_reuse_todo_ids<R: Fixture>(repository: R) {
+    let todos = create_todos(&repository, &["first", "second"]).await;
+    TodoRepository::delete(&repository, todos[1].id)
+        .await
+        .unwrap();
+
+    let [todo] = &create_todos(&repository, &["third"]).await[..] else {
+        unreachable!()
+    };
+    assert!(todo.id > todos[1].id);
+}
+
+async fn report_missing_todos<R: Fixture>(repository: R) {
+    let todos = create_todos(&repository, &["text"]).await;
+    let missing = todos[0].id + 1;
+
+    assert_not_found::<_, i32>(TodoRepository::find(&repository, missing).await);
+    let completed = UpdateTodo {
+        text: None,
+        completed: Some(true),
+    };
+    assert_not_found::<_, i32>(TodoRepository::update(&repository, missing, completed).await);
+    assert_not_found::<_, i32>(TodoRepository::delete(&repository, missing).await);
+
+    TodoRepository::delete(&repository, todos[0].id)
+        .await
+        .unwrap();
+    assert_not_found::<_, i32>(TodoRepository::delete(&repository, todos[0].id).await);
+}
+
+async fn count_todos_and_track_changes<R: Fixture>(repository: R) {
+    assert_eq!(None, repository.last_modified().await.unwrap());
+    let todos = create_todos(&repository, &["first", "second"]).await;
+    let completed = UpdateTodo {
+        text: None,
+        completed: Some(true),
+    };
+    TodoRepository::update(&repository, todos[0].id, completed)
+        .await
+        .unwrap();
+
+    let stats = repository.stats().await.unwrap();
+    assert_eq!((2, 1), (stats.total, stats.open));
+
+    for todo in &todos {
+        TodoRepository::delete(&repository, todo.id).await.unwrap();
+    }
+    let stats = repository.stats().await.unwrap();
+    assert_eq!((0, 0), (stats.total, stats.open));
+    // with no todo left, the list still changed
+    assert!(repository.last_modified().await.unwrap().is_some());
+}
+
+async fn create_todos_concurrently<R: Fixture>(repository: R) {
+    let tasks: Vec<_> = (0..20)
+        .map(|i| {
+            let repository = repository.clone();
+            tokio::spawn(async move {
+                TodoRepository::create(&repository, CreateTodo::new(format!("todo {}", i))).await
+            })
+        })
+        .collect();
+    let mut created = BTreeSet::new();
+    for task in tasks {
+        created.insert(task.await.unwrap().unwrap().id);
+    }
+    assert_eq!(20, created.len());
+
+    let all = TodoRepository::all(&repository).await.unwrap();
+    assert_eq!(created, ids(&all).into_iter().collect());
+}
+
+async fn lose_no_concurrent_update<R: Fixture>(repository: R) {
+    let todos = create_todos(&repository, &["text"]).await;
+    let id = todos[0].id;
+    let mut tasks = Vec::new();
+    for i in 0..10 {
+        let repository = repository.clone();
+        tasks.push(tokio::spawn(async move {
+            let renamed = UpdateTodo {
+                text: Some(format!("text {}", i)),
+                completed: None,
+            };
+            TodoRepository::update(&repository, id, renamed).await
+        }));
+    }
+    let completed = UpdateTodo {
+        text: None,
+        completed: Some(true),
+    };
+    TodoRepository::update(&repository, id, completed)
+        .await
+        .unwrap();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    // every update kept the field it did not change
+    let todo = TodoRepository::find(&repository, id).await.unwrap();
+    assert!(todo.completed);
+    assert!(todo.text.starts_with("text "));
+}
+
+async fn create_and_find_user<R: Fixture + UserRepository>(repository: R) {
+    let user = UserRepository::create(&repository, create_user("a@example.com"))
+        .await
+        .unwrap();
+    assert!(!user.disabled);
+
+    assert_eq!(user, repository.find_by_id(user.id).await.unwrap());
+    assert_eq!(
+        user,
+        repository.find_by_email("a@example.com").await.unwrap()
+    );
+    assert_not_found::<_, i32>(repository.find_by_id(user.id + 1).await);
+    assert_not_found::<_, String>(repository.find_by_email("b@example.com").await);
+}
+
+async fn keep_user_emails_unique<R: Fixture + UserRepository>(repository: R) {
+    let first = UserRepository::create(&repository, create_user("a@example.com"))
+        .await
+        .unwrap();
+    assert!(
+        UserRepository::create(&repository, create_user("a@example.com"))
+            .await
+            .is_err()
+    );
+    let second = UserRepository::create(&repository, create_user("b@example.com"))
+        .await
+        .unwrap();
+    let taken = UpdateUser {
+        email: Some(first.email.clone()),
+        ..UpdateUser::default()
+    };
+    assert!(UserRepository::update(&repository, second.id, taken)
+        .await
+        .is_err());
+
+    assert_eq!(
+        second,
+        repository.find_by_email("b@example.com").await.unwrap()
+    );
+}
+
+async fn update_only_given_user_fields<R: Fixture + UserRepository>(repository: R) {
+    let user = UserRepository::create(&repository, create_user("a@example.com"))
+        .await
+        .unwrap();
+    let disabled = UpdateUser {
+        disabled: Some(true),
+        ..UpdateUser::default()
+    };
+    let updated = UserRepository::update(&repository, user.id, disabled)
+        .await
+        .unwrap();
+    assert_eq!(
+        User {
+            disabled: true,
+            ..user.clone()
+        },
+        updated
+    );
+
+    let missing = UpdateUser {
+        username: Some("other".to_string()),
+        ..UpdateUser::default()
+    };
+    assert_not_found::<_, i32>(UserRepository::update(&repository, user.id + 1, missing).await);
+}
+
+async fn list_users_in_id_order<R: Fixture + UserRepository>(repository: R) {
+    let mut expected = Vec::new();
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        let user = UserRepository::create(&repository, create_user(email))
+            .await
+            .unwrap();
+        expected.push(user);
+    }
+
+    assert_eq!(expected, UserRepository::all(&repository).await.unwrap());
+}
+
+async fn delete_users<R: Fixture + UserRepository>(repository: R) {
+    let user = UserRepository::create(&repository, create_user("a@example.com"))
+        .await
+        .unwrap();
+    UserRepository::delete(&repository, user.id).await.unwrap();
+    assert_not_found::<_, i32>(repository.find_by_id(user.id).await);
+
+    // deleting is idempotent, unlike for todos, so that the admin command can be retried
+    UserRepository::delete(&repository, user.id).await.unwrap();
+    // the email is free again
+    UserRepository::create(&repository, create_user("a@example.com"))
+        .await
+        .unwrap();
+}
+
+async fn commit_transaction<R: Fixture + Transactional>(repository: R) {
+    let (todo, user) = repository
+        .transaction(|tx| {
+            Box::pin(async move {
+                let [todo] = &create_todos(tx, &["text"]).await[..] else {
+                    unreachable!()
+                };
+                let user = UserRepository::create(tx, create_user("a@example.com")).await?;
+                // the transaction sees its own changes
+                assert_eq!(*todo, TodoRepository::find(tx, todo.id).await?);
+                Ok((todo.clone(), user))
+            })
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(vec![todo], TodoRepository::all(&repository).await.unwrap());
+    assert_eq!(vec![user], UserRepository::all(&repository).await.unwrap());
+}
+
+async fn roll_back_failed_transaction<R: Fixture + Transactional>(repository: R) {
+    let todos = create_todos(&repository, &["text"]).await;
+    let id = todos[0].id;
+    let result: anyhow::Result<()> = repository
+        .transaction(move |tx| {
+            Box::pin(async move {
+                TodoRepository::delete(tx, id).await?;
+                // a transaction within it is part of it, and is rolled back along with it
+                tx.transaction(|tx| {
+                    Box::pin(async move {
+                        create_todos(tx, &["other"]).await;
+                        UserRepository::create(tx, create_user("a@example.com")).await?;
+                        Ok(())
+                    })
+                })
+                .await?;
+                anyhow::bail!("failed")
+            })
+        })
+        .await;
+    assert!(result.is_err());
+
+    assert_eq!(todos, TodoRepository::all(&repository).await.unwrap());
+    assert!(UserRepository::all(&repository).await.unwrap().is_empty());
+}
+
+/// Declares a module named after the backend with a test running each conformance test on
+/// it, with the attributes given before the name on every test.
+macro_rules! conformance_tests {
+    (@test [$($attr:meta),*] $repository:ty: $test:ident) => {
+        #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+        $(#[$attr])*
+        async fn $test() {
+            let dir = tempfile::tempdir().unwrap();
+            let repository = <$repository as Fixture>::open(dir.path()).await;
+            super::$test(repository.clone()).await;
+            repository.close().await;
+        }
+    };
+    (@tests $attrs:tt $repository:ty: $($test:ident),* $(,)?) => {
+        $(conformance_tests!(@test $attrs $repository: $test);)*
+    };
+    (@todo_tests $attrs:tt $repository:ty) => {
+        conformance_tests!(
+            @tests $attrs $repository:
+            create_and_find_todo,
+            update_only_given_todo_fields,
+            list_newest_todo_first,
+            never_reuse_todo_ids,
+            report_missing_todos,
+            count_todos_and_track_changes,
+            create_todos_concurrently,
+            lose_no_concurrent_update,
+        );
+    };
+    // a backend that only stores todos
+    ($(#[$attr:meta])* todos $backend:ident: $repository:ty) => {
+        mod $backend {
+            use super::*;
+
+            conformance_tests!(@todo_tests [$($attr),*] $repository);
+        }
+    };
+    ($(#[$attr:meta])* $backend:ident: $repository:ty) => {
+        mod $backend {
+            use super::*;
+
+            conformance_tests!(@todo_tests [$($attr),*] $repository);
+            conformance_tests!(
+                @tests [$($attr),*] $repository:
+                create_and_find_user,
+                keep_user_emails_unique,
+                update_only_given_user_fields,
+                list_users_in_id_order,
+                delete_users,
+                commit_transaction,
+                roll_back_failed_transaction,
+            );
+        }
+    };
+}
+
+conformance_tests!(hash_map: HashMapRepository);
+conformance_tests!(sqlite: SqliteRepository);
+conformance_tests!(file: FileRepository);
+conformance_tests!(todos event_sourced: EventSourcedRepository);
+conformance_tests!(
+    #[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+    postgres: PostgresRepository
+);
@@ -0,0 +1,636 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::config::DatabaseConfig;
+use crate::events::{EventBus, TodoEvent};
+use crate::repositories::file::{self, directory, write_atomically};
+use crate::repositories::todos::{Change, StoredEvent, Todo};
+use crate::repositories::{Clock, RepositoryError};
+
+const LOG_FILE: &str = "events.log";
+const SNAPSHOT_FILE: &str = "projection.json";
+
+/// The subdirectory of an `events:` directory the todo streams are kept in, next to the files
+/// of the `FileRepository` holding users and webhooks.
+pub(crate) const TODOS_DIR: &str = "todos";
+
+/// Events the stream takes before the projection is snapshotted again.
+const SNAPSHOT_EVERY: u64 = 1000;
+
+/// A todo as its events left it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProjectedTodo {
+    todo: Todo,
+    version: u32,
+}
+
+/// The current state of the todos, folded from the stream.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Projection {
+    /// The position of the last event applied.
+    position: u64,
+    todos: BTreeMap<i32, ProjectedTodo>,
+    /// The highest id handed out, which deleted todos keep.
+    last_id: i32,
+    last_deleted: Option<DateTime<Utc>>,
+    /// Where the lines holding the events of each todo, deleted ones included, are in the
+    /// log, so that a history is read without going through the whole log.
+    streams: BTreeMap<i32, Vec<Range<usize>>>,
+}
+
+impl Projection {
+    /// Applies the changes in `bytes`, each one a line of events, which start at `offset` of
+    /// the log, returning how many there were and where the last one ends.
+    fn replay(&mut self, bytes: &[u8], offset: usize) -> anyhow::Result<(usize, usize)> {
+        file::replay(bytes, |events: Vec<StoredEvent>, line| {
+            events.iter().try_for_each(|event| self.apply(event))?;
+            self.index(&events, offset + line.start..offset + line.end);
+            Ok(())
+        })
+    }
+
+    /// Notes that the events of one change are at `line` of the log.
+    fn index(&mut self, events: &[StoredEvent], line: Range<usize>) {
+        if let Some(event) = events.first() {
+            self.streams.entry(event.todo_id).or_default().push(line);
+        }
+    }
+
+    fn apply(&mut self, event: &StoredEvent) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            event.position == self.position + 1,
+            "event {} follows event {}",
+            event.position,
+            self.position
+        );
+        let id = event.todo_id;
+        if let Change::TodoCreated { text } = &event.change {
+            anyhow::ensure!(id > self.last_id, "todo {} is created twice", id);
+            let todo = Todo {
+                id,
+                text: text.clone(),
+                completed: false,
+                updated_at: event.at,
+            };
+            self.todos.insert(id, ProjectedTodo { todo, version: 1 });
+            self.last_id = id;
+            self.position = event.position;
+            return Ok(());
+        }
+        let projected = self
+            .todos
+            .get_mut(&id)
+            .with_context(|| format!("event {} changes the missing todo {}", event.position, id))?;
+        anyhow::ensure!(
+            event.version == projected.version + 1,
+            "event {} is version {} of todo {}, which is at version {}",
+            event.position,
+            event.version,
+            id,
+            projected.version
+        );
+        let todo = &mut projected.todo;
+        match &event.change {
+            Change::TodoCreated { .. } => unreachable!("handled above"),
+            Change::TextChanged { text } => todo.text = text.clone(),
+            Change::Completed => todo.completed = true,
+            Change::Reopened => todo.completed = false,
+            Change::Deleted => {
+                self.todos.remove(&id);
+                self.last_deleted = self.last_deleted.max(Some(event.at));
+                self.position = event.position;
+                return Ok(());
+            }
+        }
+        todo.updated_at = event.at;
+        projected.version = event.version;
+        self.position = event.position;
+        Ok(())
+    }
+
+    pub(crate) fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        let projected = self
+            .todos
+            .get(&id)
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        Ok(projected.todo.clone())
+    }
+
+    /// Every todo, the most recent first.
+    pub(crate) fn todos(&self) -> impl Iterator<Item = &Todo> {
+        self.todos.values().rev().map(|projected| &projected.todo)
+    }
+
+    /// How many events the projection was folded from.
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub(crate) fn last_id(&self) -> i32 {
+        self.last_id
+    }
+
+    pub(crate) fn last_deleted(&self) -> Option<DateTime<Utc>> {
+        self.last_deleted
+    }
+}
+
+/// The projection, and how much of the log it was folded from.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    offset: usize,
+    projection: Projection,
+}
+
+/// Todos kept as the stream of events that made them, in a local directory.
+///
+/// Every change appends its events to the log as one line, synced before it is acknowledged,
+/// and then applies them to the projection the reads are served from. The log is never
+/// rewritten: the projection is snapshotted every `SNAPSHOT_EVERY` events instead, and
+/// opening the directory replays the log from the snapshot on. The projection also keeps
+/// where each todo's events are in the log, which histories are read from.
+///
+/// Like `FileRepository`, it publishes events on the `EventBus` once the change is on disk.
+#[derive(Debug, Clone)]
+pub(crate) struct EventSourcedRepository {
+    projection: Arc<RwLock<Projection>>,
+    log: Arc<Mutex<EventLog>>,
+    /// The log, which histories are read from.
+    path: Arc<Path>,
+    events: EventBus,
+    clock: Clock,
+}
+
+impl EventSourcedRepository {
+    /// Opens the todos of the directory named by the database url, e.g.
+    /// `events:///var/lib/todos`.
+    pub(crate) async fn open(config: &DatabaseConfig, events: EventBus) -> anyhow::Result<Self> {
+        let dir = directory(config).join(TODOS_DIR);
+        Self::open_with(&dir, events, Clock::default(), SNAPSHOT_EVERY, false).await
+    }
+
+    /// Opens the todos like `open`, but folds the projection from the whole log instead of
+    /// the snapshot, which it replaces: e.g. after the snapshot was lost or corrupted, or the
+    /// way events are applied changed.
+    pub(crate) async fn rebuild(config: &DatabaseConfig, events: EventBus) -> anyhow::Result<Self> {
+        let dir = directory(config).join(TODOS_DIR);
+        Self::open_with(&dir, events, Clock::default(), SNAPSHOT_EVERY, true).await
+    }
+
+    async fn open_with(
+        dir: &Path,
+        events: EventBus,
+        clock: Clock,
+        snapshot_every: u64,
+        rebuild: bool,
+    ) -> anyhow::Result<Self> {
+        tracing::debug!("start opening [{}]...", dir.display());
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create [{}]", dir.display()))?;
+        let lock = file::lock(dir)?;
+        let Snapshot {
+            offset,
+            mut projection,
+        } = match std::fs::read(dir.join(SNAPSHOT_FILE)) {
+            _ if rebuild => Snapshot::default(),
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .context("failed to read the snapshot, rebuild the projection")?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(e).context("failed to read the snapshot"),
+        };
+        let path = dir.join(LOG_FILE);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context("failed to read the event log"),
+        };
+        anyhow::ensure!(
+            offset <= bytes.len(),
+            "the snapshot is ahead of the event log, rebuild the projection"
+        );
+        let snapshot_position = projection.position;
+        let (changes, end) = projection
+            .replay(&bytes[offset..], offset)
+            .context("failed to replay the event log")?;
+        let end = offset + end;
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .context("failed to open the event log")?;
+        if end < bytes.len() {
+            tracing::warn!(
+                "dropping the last {} bytes of the event log, a write torn by a crash",
+                bytes.len() - end
+            );
+            file.set_len(end as u64).await?;
+            file.sync_data().await?;
+        }
+        tracing::info!(
+            "replayed {} changes of the event log since the snapshot",
+            changes
+        );
+        let mut log = EventLog {
+            dir: dir.to_path_buf(),
+            file,
+            length: end,
+            snapshot_position,
+            snapshot_every,
+            failed: false,
+            _lock: lock,
+        };
+        if rebuild {
+            log.snapshot(projection.clone()).await?;
+        }
+        Ok(Self {
+            projection: Arc::new(RwLock::new(projection)),
+            log: Arc::new(Mutex::new(log)),
+            path: path.into(),
+            events,
+            clock,
+        })
+    }
+
+    /// Takes the log for a change, so that the events of changes are appended in the order
+    /// the changes are made.
+    pub(crate) async fn log(&self) -> anyhow::Result<MutexGuard<'_, EventLog>> {
+        let log = self.log.lock().await;
+        anyhow::ensure!(
+            !log.failed,
+            "a write to the event log failed, restart to recover the todos from it"
+        );
+        Ok(log)
+    }
+
+    pub(crate) fn read_projection(&self) -> RwLockReadGuard<'_, Projection> {
+        self.projection.read().unwrap()
+    }
+
+    /// Appends `changes` to the stream of the todo `id` and applies them, returning the todo
+    /// they leave behind, if any.
+    pub(crate) async fn record(
+        &self,
+        log: &mut EventLog,
+        id: i32,
+        changes: Vec<Change>,
+    ) -> anyhow::Result<Option<Todo>> {
+        let at = self.clock.now();
+        let events: Vec<StoredEvent> = {
+            let projection = self.read_projection();
+            let version = projection
+                .todos
+                .get(&id)
+                .map_or(0, |projected| projected.version);
+            changes
+                .into_iter()
+                .zip(1..)
+                .map(|(change, i)| StoredEvent {
+                    position: projection.position + i as u64,
+                    todo_id: id,
+                    version: version + i,
+                    at,
+                    change,
+                })
+                .collect()
+        };
+        let line = log.append(&events).await?;
+        let projection = {
+            let mut projection = self.projection.write().unwrap();
+            for event in &events {
+                projection
+                    .apply(event)
+                    .expect("a change is checked before it is recorded");
+            }
+            projection.index(&events, line);
+            if projection.position - log.snapshot_position >= log.snapshot_every {
+                Some(projection.clone())
+            } else {
+                None
+            }
+        };
+        if let Some(projection) = projection {
+            if let Err(e) = log.snapshot(projection).await {
+                tracing::warn!("failed to snapshot the projection: {:#}", e);
+            }
+        }
+        Ok(self.read_projection().find(id).ok())
+    }
+
+    /// The events of the todo `id`, oldest first, including those of a deleted todo.
+    ///
+    /// Only the lines of the todo are read, at the offsets the projection keeps for them.
+    /// Lines the projection knows of are complete and never rewritten, so they are read
+    /// without taking the log.
+    pub(crate) async fn history(&self, id: i32) -> anyhow::Result<Vec<StoredEvent>> {
+        let lines = self
+            .read_projection()
+            .streams
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        let mut file = tokio::fs::File::open(&*self.path)
+            .await
+            .context("failed to open the event log")?;
+        let mut history = Vec::new();
+        let mut line = Vec::new();
+        for range in lines {
+            line.resize(range.len(), 0);
+            file.seek(SeekFrom::Start(range.start as u64)).await?;
+            file.read_exact(&mut line)
+                .await
+                .context("failed to read the event log")?;
+            let events: Vec<StoredEvent> = serde_json::from_slice(&line)
+                .with_context(|| format!("corrupt record at byte {} of the log", range.start))?;
+            history.extend(events);
+        }
+        Ok(history)
+    }
+
+    /// The events taken since the last snapshot, or why changes are refused.
+    pub(crate) async fn check(&self) -> anyhow::Result<u64> {
+        let log = self.log().await?;
+        Ok(self.read_projection().position() - log.snapshot_position)
+    }
+
+    pub(crate) fn publish(&self, event: TodoEvent) {
+        self.events.publish(event);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_now(&self, now: DateTime<Utc>) {
+        self.clock.set(now);
+    }
+}
+
+/// The log of every event, and the lock on the directory for as long as it is open.
+#[derive(Debug)]
+pub(crate) struct EventLog {
+    dir: PathBuf,
+    file: tokio::fs::File,
+    /// Where the last complete change ends.
+    length: usize,
+    /// The position the snapshot was taken at.
+    snapshot_position: u64,
+    snapshot_every: u64,
+    /// What follows a failed write may be torn, so nothing is appended after it.
+    failed: bool,
+    _lock: File,
+}
+
+impl EventLog {
+    /// Appends the events of one change as a line and syncs it, returning where the line
+    /// is, without its newline.
+    async fn append(&mut self, events: &[StoredEvent]) -> anyhow::Result<Range<usize>> {
+        let mut line = serde_json::to_vec(events)?;
+        line.push(b'\n');
+        if let Err(e) = self.write(&line).await {
+            self.failed = true;
+            return Err(e).context("failed to write to the event log");
+        }
+        let start = self.length;
+        self.length += line.len();
+        Ok(start..self.length - 1)
+    }
+
+    async fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        self.file.write_all(line).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await
+    }
+
+    /// Writes `projection`, which is folded from the whole log, to the snapshot.
+    async fn snapshot(&mut self, projection: Projection) -> anyhow::Result<()> {
+        let position = projection.position;
+        let snapshot = serde_json::to_vec(&Snapshot {
+            offset: self.length,
+            projection,
+        })?;
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || write_atomically(&dir, SNAPSHOT_FILE, &snapshot))
+            .await?
+            .context("failed to write the snapshot")?;
+        tracing::debug!("snapshotted the projection at event {}", position);
+        self.snapshot_position = position;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+    use crate::repositories::hash_map::test_utils::start_time;
+
+    /// A repository on `dir` whose clock stands at `start_time()`, snapshotting every
+    /// `snapshot_every` events.
+    pub(crate) async fn open(dir: &Path, snapshot_every: u64) -> EventSourcedRepository {
+        EventSourcedRepository::open_with(
+            dir,
+            EventBus::default(),
+            Clock::stopped_at(start_time()),
+            snapshot_every,
+            false,
+        )
+        .await
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::test_utils::open;
+    use super::*;
+    use crate::repositories::todos::{CreateTodo, TodoRepository, UpdateTodo};
+
+    async fn fill(repository: &EventSourcedRepository) {
+        for text in ["first", "second", "third"] {
+            repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+        let payload = UpdateTodo {
+            text: Some("renamed".to_string()),
+            completed: Some(true),
+        };
+        repository.update(1, payload).await.unwrap();
+        let reopened = UpdateTodo {
+            text: None,
+            completed: Some(false),
+        };
+        repository.update(1, reopened).await.unwrap();
+        repository.delete(3).await.unwrap();
+    }
+
+    async fn assert_filled(repository: &EventSourcedRepository) {
+        let texts: Vec<String> = repository
+            .all()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|todo| todo.text)
+            .collect();
+        assert_eq!(vec!["second", "renamed"], texts);
+        assert!(!repository.find(1).await.unwrap().completed);
+        assert!(repository.last_modified().await.unwrap().is_some());
+        // the id of the deleted todo is not handed out again
+        let todo = repository
+            .create(CreateTodo::new("fourth".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(4, todo.id);
+    }
+
+    #[tokio::test]
+    async fn replay_events_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        fill(&open(dir.path(), SNAPSHOT_EVERY).await).await;
+
+        assert_filled(&open(dir.path(), SNAPSHOT_EVERY).await).await;
+    }
+
+    #[tokio::test]
+    async fn replay_events_after_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), 4).await;
+        fill(&repository).await;
+        // the update of todo 1 is two events, the snapshot was taken after it
+        assert_eq!(2, repository.check().await.unwrap());
+        drop(repository);
+
+        assert!(dir.path().join(SNAPSHOT_FILE).exists());
+        assert_filled(&open(dir.path(), 4).await).await;
+    }
+
+    #[tokio::test]
+    async fn record_todo_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), SNAPSHOT_EVERY).await;
+        fill(&repository).await;
+
+        let changes: Vec<(u64, u32, Change)> = repository
+            .history(1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.position, event.version, event.change))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    1,
+                    1,
+                    Change::TodoCreated {
+                        text: "first".to_string()
+                    }
+                ),
+                (
+                    4,
+                    2,
+                    Change::TextChanged {
+                        text: "renamed".to_string()
+                    }
+                ),
+                (5, 3, Change::Completed),
+                (6, 4, Change::Reopened),
+            ],
+            changes
+        );
+        let deleted = repository.history(3).await.unwrap();
+        assert_eq!(Change::Deleted, deleted.last().unwrap().change);
+        assert!(repository.history(5).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_history_after_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), 4).await;
+        fill(&repository).await;
+        let history = repository.history(1).await.unwrap();
+        drop(repository);
+
+        let repository = open(dir.path(), 4).await;
+        assert_eq!(history, repository.history(1).await.unwrap());
+        assert_eq!(2, repository.history(3).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn rebuild_projection_from_scratch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), 4).await;
+        fill(&repository).await;
+        drop(repository);
+        // a snapshot that no longer matches the log, e.g. written by a faulty release
+        let path = dir.path().join(SNAPSHOT_FILE);
+        let mut snapshot: Snapshot =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        snapshot.projection.todos.get_mut(&2).unwrap().todo.text = "garbled".to_string();
+        std::fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        let repository = open(dir.path(), SNAPSHOT_EVERY).await;
+        assert_eq!("garbled", repository.find(2).await.unwrap().text);
+        drop(repository);
+
+        let repository = EventSourcedRepository::open_with(
+            dir.path(),
+            EventBus::default(),
+            Clock::default(),
+            SNAPSHOT_EVERY,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(7, repository.read_projection().position());
+        assert_eq!(0, repository.check().await.unwrap());
+        drop(repository);
+        assert_filled(&open(dir.path(), SNAPSHOT_EVERY).await).await;
+    }
+
+    #[tokio::test]
+    async fn drop_torn_write_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        fill(&open(dir.path(), SNAPSHOT_EVERY).await).await;
+        let log = dir.path().join(LOG_FILE);
+        let length = std::fs::metadata(&log).unwrap().len();
+        let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(br#"[{"position":8,"todo_id":4,"ver"#)
+            .unwrap();
+        drop(file);
+
+        let repository = open(dir.path(), SNAPSHOT_EVERY).await;
+        assert_eq!(length, std::fs::metadata(&log).unwrap().len());
+        assert_filled(&repository).await;
+    }
+
+    #[tokio::test]
+    async fn refuse_events_out_of_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fill(&open(dir.path(), SNAPSHOT_EVERY).await).await;
+        let log = dir.path().join(LOG_FILE);
+        let bytes = std::fs::read(&log).unwrap();
+        let mut lines: Vec<&[u8]> = bytes.split_inclusive(|byte| *byte == b'\n').collect();
+        lines.swap(0, 1);
+        std::fs::write(&log, lines.concat()).unwrap();
+
+        let result = EventSourcedRepository::open_with(
+            dir.path(),
+            EventBus::default(),
+            Clock::default(),
+            SNAPSHOT_EVERY,
+            true,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,694 @@
+use std::fs::{File, TryLockError};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::config::DatabaseConfig;
+use crate::events::{EventBus, Publisher, TodoEvent};
+use crate::repositories::hash_map::{HashMapRepository, Table};
+use crate::repositories::todos::Todo;
+use crate::repositories::users::User;
+use crate::repositories::webhooks::{Webhook, WebhookDelivery};
+use crate::repositories::Transactional;
+
+const LOG_FILE: &str = "wal.log";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOCK_FILE: &str = "lock";
+
+/// Records the log takes before it is compacted into the snapshot.
+const COMPACT_AFTER: usize = 1000;
+
+/// Everything in a local directory, for single-node installs without a database server.
+///
+/// The data is held in memory. Every change is appended to a write-ahead log and synced
+/// before it is made to the data, so that no reader sees a change a crash could take back,
+/// and the log is compacted into a snapshot every
+/// `COMPACT_AFTER` records. Opening the directory replays the snapshot and then the log,
+/// dropping the torn write a crash can leave at its end.
+///
+/// Like `SqliteRepository`, it publishes events on the `EventBus` once the change is on disk.
+#[derive(Debug, Clone)]
+pub(crate) struct FileRepository {
+    pub(crate) data: HashMapRepository,
+    log: Arc<Mutex<Log>>,
+    events: Publisher,
+}
+
+impl FileRepository {
+    /// Opens the directory named by the database url, e.g. `file:///var/lib/todos`.
+    pub(crate) async fn open(config: &DatabaseConfig, events: EventBus) -> anyhow::Result<Self> {
+        let data = HashMapRepository::with_events(EventBus::default());
+        Self::open_with(&directory(config), data, events, COMPACT_AFTER).await
+    }
+
+    /// Loads the directory into `data`, whose own events go nowhere: they would be published
+    /// before the change is on disk.
+    async fn open_with(
+        dir: &Path,
+        data: HashMapRepository,
+        events: EventBus,
+        compact_after: usize,
+    ) -> anyhow::Result<Self> {
+        tracing::debug!("start opening [{}]...", dir.display());
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create [{}]", dir.display()))?;
+        let lock = lock(dir)?;
+        match std::fs::read(dir.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => serde_json::from_slice::<Snapshot>(&bytes)
+                .context("failed to read the snapshot")?
+                .restore(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("failed to read the snapshot"),
+        }
+        let path = dir.join(LOG_FILE);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context("failed to read the log"),
+        };
+        let (records, end) = replay(&bytes, |record: Record, _| {
+            record.apply(&data);
+            Ok(())
+        })?;
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .context("failed to open the log")?;
+        if end < bytes.len() {
+            tracing::warn!(
+                "dropping the last {} bytes of the log, a write torn by a crash",
+                bytes.len() - end
+            );
+            file.set_len(end as u64).await?;
+            file.sync_data().await?;
+        }
+        tracing::info!("replayed {} records of the log", records);
+        let log = LogFile {
+            dir: dir.to_path_buf(),
+            file,
+            records,
+            compact_after,
+            failed: false,
+            _lock: lock,
+        };
+        Ok(Self {
+            data,
+            log: Arc::new(Mutex::new(Log::File(log))),
+            events: Publisher::Bus(events),
+        })
+    }
+
+    /// Takes the log for a change, so that changes reach it in the order they are made.
+    ///
+    /// The change is worked out from `data`, which no one else changes while the log is taken,
+    /// and then made with `Log::append`.
+    pub(crate) async fn log(&self) -> anyhow::Result<MutexGuard<'_, Log>> {
+        let log = self.log.lock().await;
+        if let Log::File(file) = &*log {
+            anyhow::ensure!(
+                !file.failed,
+                "a write to the log failed, restart to recover the data from it"
+            );
+        }
+        Ok(log)
+    }
+
+    /// The records taken since the last snapshot, or why changes are refused.
+    pub(crate) async fn check(&self) -> anyhow::Result<usize> {
+        Ok(match &*self.log().await? {
+            Log::File(file) => file.records,
+            Log::Batch(records) => records.len(),
+        })
+    }
+
+    pub(crate) fn publish(&self, event: TodoEvent) {
+        self.events.publish(event);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_now(&self, now: DateTime<Utc>) {
+        self.data.set_now(now);
+    }
+}
+
+/// The directory of a `file:` or `events:` url; `file:data` and `file://data` are relative
+/// to the working directory.
+pub(crate) fn directory(config: &DatabaseConfig) -> PathBuf {
+    let url = &config.url;
+    let path = ["file://", "file:", "events://", "events:"]
+        .into_iter()
+        .find_map(|scheme| url.strip_prefix(scheme))
+        .unwrap_or(url);
+    PathBuf::from(path)
+}
+
+/// Keeps other processes, e.g. an admin command next to the server, out of the directory.
+pub(crate) fn lock(dir: &Path) -> anyhow::Result<File> {
+    let path = dir.join(LOCK_FILE);
+    let file =
+        File::create(&path).with_context(|| format!("failed to create [{}]", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => {
+            anyhow::bail!("[{}] is in use by another process", dir.display())
+        }
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("failed to lock [{}]", path.display()))
+        }
+    }
+}
+
+/// Applies the records of the log in `bytes`, one JSON document per line, along with where
+/// its line is in `bytes`, returning how many there were and where the last one ends.
+///
+/// Only the last record can be incomplete, as every one before it was synced; it is left
+/// out. An unreadable record anywhere else means the log is corrupt.
+pub(crate) fn replay<R: DeserializeOwned>(
+    bytes: &[u8],
+    mut apply: impl FnMut(R, Range<usize>) -> anyhow::Result<()>,
+) -> anyhow::Result<(usize, usize)> {
+    let mut records = 0;
+    let mut end = 0;
+    while let Some(length) = bytes[end..].iter().position(|byte| *byte == b'\n') {
+        let next = end + length + 1;
+        match serde_json::from_slice::<R>(&bytes[end..end + length]) {
+            Ok(record) => apply(record, end..end + length)
+                .with_context(|| format!("invalid record at byte {} of the log", end))?,
+            Err(_) if next == bytes.len() => break,
+            Err(e) => {
+                return Err(e).with_context(|| format!("corrupt record at byte {} of the log", end))
+            }
+        }
+        records += 1;
+        end = next;
+    }
+    Ok((records, end))
+}
+
+#[async_trait]
+impl Transactional for FileRepository {
+    /// Runs `work` on a copy of the data, whose changes are appended to the log as one record
+    /// and only then made to the data. Other changes wait for the transaction.
+    async fn transaction<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'r> FnOnce(&'r Self) -> BoxFuture<'r, anyhow::Result<T>> + Send + 'static,
+    {
+        let mut log = self.log().await?;
+        if let Log::Batch(_) = &*log {
+            drop(log);
+            return work(self).await;
+        }
+        // the records of `work` make the same changes to `data` as it makes to the copy
+        let repository = FileRepository {
+            data: self.data.scratch().repository,
+            log: Arc::new(Mutex::new(Log::Batch(Vec::new()))),
+            events: Publisher::deferred(),
+        };
+        let value = work(&repository).await?;
+        let records = match &mut *repository.log.lock().await {
+            Log::Batch(records) => std::mem::take(records),
+            Log::File(_) => unreachable!("a transaction logs to a batch"),
+        };
+        if !records.is_empty() {
+            log.append(Record::Batch { records }, &self.data).await?;
+        }
+        for event in repository.events.take() {
+            self.publish(event);
+        }
+        Ok(value)
+    }
+}
+
+/// Where a repository's changes go: the write-ahead log, or the transaction it works in.
+#[derive(Debug)]
+pub(crate) enum Log {
+    File(LogFile),
+    Batch(Vec<Record>),
+}
+
+impl Log {
+    /// Makes the change of `record` to `data` once it is in the log; see `LogFile::append`.
+    pub(crate) async fn append(
+        &mut self,
+        record: Record,
+        data: &HashMapRepository,
+    ) -> anyhow::Result<()> {
+        match self {
+            Log::File(file) => file.append(record, data).await,
+            Log::Batch(records) => {
+                record.clone().apply(data);
+                records.push(record);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The write-ahead log, and the lock on the directory for as long as it is open.
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    dir: PathBuf,
+    file: tokio::fs::File,
+    records: usize,
+    compact_after: usize,
+    /// What follows a failed write may be torn, so nothing is appended after it.
+    failed: bool,
+    _lock: File,
+}
+
+impl LogFile {
+    /// Appends `record` and syncs it, then applies it to `data`, compacting the log when it
+    /// is due.
+    pub(crate) async fn append(
+        &mut self,
+        record: Record,
+        data: &HashMapRepository,
+    ) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        if let Err(e) = self.write(&line).await {
+            // the record may still have reached the disk, so no later one is appended after it
+            self.failed = true;
+            return Err(e).context("failed to write to the log");
+        }
+        record.apply(data);
+        self.records += 1;
+        if self.records >= self.compact_after {
+            if let Err(e) = self.compact(data).await {
+                tracing::warn!("failed to compact the log: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        self.file.write_all(line).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await
+    }
+
+    /// Writes all of `data` to the snapshot and empties the log.
+    ///
+    /// A crash in between leaves records in the log that the snapshot has already, which
+    /// is fine since replaying a record again changes nothing.
+    async fn compact(&mut self, data: &HashMapRepository) -> anyhow::Result<()> {
+        let snapshot = serde_json::to_vec(&Snapshot::take(data))?;
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || write_atomically(&dir, SNAPSHOT_FILE, &snapshot))
+            .await?
+            .context("failed to write the snapshot")?;
+        self.file.set_len(0).await?;
+        self.file.sync_data().await?;
+        tracing::debug!("compacted {} records of the log", self.records);
+        self.records = 0;
+        Ok(())
+    }
+}
+
+/// Replaces the file `name` in `dir` with `bytes`, leaving either the old or the new content
+/// after a crash.
+pub(crate) fn write_atomically(dir: &Path, name: &str, bytes: &[u8]) -> io::Result<()> {
+    let path = dir.join(name);
+    let temporary = path.with_extension("tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, &path)?;
+    // the rename is only durable once the directory is
+    File::open(dir)?.sync_all()
+}
+
+/// A webhook as written to disk, where its secret is kept unlike in responses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredWebhook {
+    id: i32,
+    user_id: i32,
+    url: String,
+    secret: String,
+    events: Vec<String>,
+}
+
+impl From<Webhook> for StoredWebhook {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            user_id: webhook.user_id,
+            url: webhook.url,
+            secret: webhook.secret,
+            events: webhook.events,
+        }
+    }
+}
+
+impl From<StoredWebhook> for Webhook {
+    fn from(webhook: StoredWebhook) -> Self {
+        Self {
+            id: webhook.id,
+            user_id: webhook.user_id,
+            url: webhook.url,
+            secret: webhook.secret,
+            events: webhook.events,
+        }
+    }
+}
+
+/// One change in the log, as the rows it leaves behind, so that applying it again changes
+/// nothing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Record {
+    Todo(Todo),
+    TodoDeleted {
+        id: i32,
+        at: DateTime<Utc>,
+    },
+    User(User),
+    UserDeleted {
+        id: i32,
+    },
+    Webhook(StoredWebhook),
+    WebhookDeleted {
+        id: i32,
+    },
+    Delivery(WebhookDelivery),
+    /// The changes of a transaction, which are applied together.
+    Batch {
+        records: Vec<Record>,
+    },
+}
+
+impl Record {
+    fn apply(self, data: &HashMapRepository) {
+        match self {
+            Record::Todo(todo) => data.write_store_ref().todos.restore(todo.id, todo),
+            Record::TodoDeleted { id, at } => {
+                let mut store = data.write_store_ref();
+                store.todos.rows.remove(&id);
+                store.last_deleted = store.last_deleted.max(Some(at));
+            }
+            Record::User(user) => data.write_user_store_ref().restore(user.id, user),
+            Record::UserDeleted { id } => data.remove_user(id),
+            Record::Webhook(webhook) => data
+                .write_webhook_store_ref()
+                .webhooks
+                .restore(webhook.id, webhook.into()),
+            Record::WebhookDeleted { id } => data.write_webhook_store_ref().remove(id),
+            Record::Delivery(delivery) => data
+                .write_webhook_store_ref()
+                .deliveries
+                .restore(delivery.id, delivery),
+            Record::Batch { records } => {
+                for record in records {
+                    record.apply(data);
+                }
+            }
+        }
+    }
+}
+
+/// The rows of a table, and its last id in case the rows holding the highest ids are gone.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredTable<T> {
+    last_id: i32,
+    rows: Vec<T>,
+}
+
+impl<T> StoredTable<T> {
+    fn take<R: Clone>(table: &Table<R>, into: impl Fn(R) -> T) -> Self {
+        Self {
+            last_id: table.last_id(),
+            rows: table.rows.values().cloned().map(into).collect(),
+        }
+    }
+
+    fn restore<R>(self, table: &mut Table<R>, id: impl Fn(&T) -> i32, from: impl Fn(T) -> R) {
+        table.skip_to(self.last_id);
+        for row in self.rows {
+            table.restore(id(&row), from(row));
+        }
+    }
+}
+
+/// All of the data, which the log continues from.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    todos: StoredTable<Todo>,
+    last_deleted: Option<DateTime<Utc>>,
+    users: StoredTable<User>,
+    webhooks: StoredTable<StoredWebhook>,
+    deliveries: StoredTable<WebhookDelivery>,
+}
+
+impl Snapshot {
+    fn take(data: &HashMapRepository) -> Self {
+        let store = data.read_store_ref();
+        let webhook_store = data.read_webhook_store_ref();
+        Self {
+            todos: StoredTable::take(&store.todos, |todo| todo),
+            last_deleted: store.last_deleted,
+            users: StoredTable::take(&data.read_user_store_ref(), |user| user),
+            webhooks: StoredTable::take(&webhook_store.webhooks, StoredWebhook::from),
+            deliveries: StoredTable::take(&webhook_store.deliveries, |delivery| delivery),
+        }
+    }
+
+    fn restore(self, data: &HashMapRepository) {
+        let mut store = data.write_store_ref();
+        self.todos
+            .restore(&mut store.todos, |todo| todo.id, |todo| todo);
+        store.last_deleted = self.last_deleted;
+        self.users.restore(
+            &mut data.write_user_store_ref(),
+            |user| user.id,
+            |user| user,
+        );
+        let mut webhook_store = data.write_webhook_store_ref();
+        self.webhooks.restore(
+            &mut webhook_store.webhooks,
+            |webhook| webhook.id,
+            Webhook::from,
+        );
+        self.deliveries.restore(
+            &mut webhook_store.deliveries,
+            |delivery| delivery.id,
+            |delivery| delivery,
+        );
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+
+    /// A repository on `dir` whose clock stands at `start_time()`, compacting after
+    /// `compact_after` records.
+    pub(crate) async fn open(dir: &Path, compact_after: usize) -> FileRepository {
+        FileRepository::open_with(
+            dir,
+            HashMapRepository::new(),
+            EventBus::default(),
+            compact_after,
+        )
+        .await
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::open;
+    use super::*;
+    use crate::repositories::todos::{CreateTodo, TodoRepository, UpdateTodo};
+    use crate::repositories::users::{CreateUser, UserRepository};
+    use crate::repositories::webhooks::{CreateWebhook, WebhookRepository};
+
+    async fn fill(repository: &FileRepository) {
+        for text in ["first", "second", "third"] {
+            TodoRepository::create(repository, CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+        let completed = UpdateTodo {
+            text: None,
+            completed: Some(true),
+        };
+        TodoRepository::update(repository, 1, completed)
+            .await
+            .unwrap();
+        TodoRepository::delete(repository, 3).await.unwrap();
+        let user = CreateUser {
+            username: "user".to_string(),
+            email: "user@example.com".to_string(),
+            password_hash: "hash".to_string(),
+        };
+        let user = UserRepository::create(repository, user).await.unwrap();
+        let webhook = CreateWebhook::new(
+            "http://localhost/hook".to_string(),
+            "0123456789abcdef".to_string(),
+            vec![],
+        );
+        WebhookRepository::create(repository, user.id, webhook)
+            .await
+            .unwrap();
+    }
+
+    async fn assert_filled(repository: &FileRepository) {
+        let texts: Vec<String> = TodoRepository::all(repository)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|todo| todo.text)
+            .collect();
+        assert_eq!(vec!["second", "first"], texts);
+        assert!(TodoRepository::find(repository, 1).await.unwrap().completed);
+        assert!(repository.last_modified().await.unwrap().is_some());
+        let user = repository.find_by_email("user@example.com").await.unwrap();
+        let webhooks = WebhookRepository::all(repository, user.id).await.unwrap();
+        assert_eq!("0123456789abcdef", webhooks[0].secret);
+        // the id of the deleted todo is not handed out again
+        let todo = TodoRepository::create(repository, CreateTodo::new("fourth".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(4, todo.id);
+    }
+
+    #[tokio::test]
+    async fn replay_log_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        fill(&open(dir.path(), COMPACT_AFTER).await).await;
+
+        assert_filled(&open(dir.path(), COMPACT_AFTER).await).await;
+    }
+
+    #[tokio::test]
+    async fn compact_log_into_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), 4).await;
+        fill(&repository).await;
+        assert_eq!(3, repository.check().await.unwrap());
+        drop(repository);
+
+        assert!(dir.path().join(SNAPSHOT_FILE).exists());
+        assert_filled(&open(dir.path(), 4).await).await;
+    }
+
+    #[tokio::test]
+    async fn drop_torn_write_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        fill(&open(dir.path(), COMPACT_AFTER).await).await;
+        let log = dir.path().join(LOG_FILE);
+        let length = std::fs::metadata(&log).unwrap().len();
+        let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(br#"{"type":"todo","id":4,"te"#).unwrap();
+        drop(file);
+
+        let repository = open(dir.path(), COMPACT_AFTER).await;
+        assert_eq!(length, std::fs::metadata(&log).unwrap().len());
+        assert_filled(&repository).await;
+        drop(repository);
+        assert_eq!(
+            3,
+            TodoRepository::all(&open(dir.path(), COMPACT_AFTER).await)
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn refuse_corrupt_log() {
+        let dir = tempfile::tempdir().unwrap();
+        fill(&open(dir.path(), COMPACT_AFTER).await).await;
+        let log = dir.path().join(LOG_FILE);
+        let mut bytes = std::fs::read(&log).unwrap();
+        bytes[1] = b'#';
+        std::fs::write(&log, bytes).unwrap();
+
+        let result = FileRepository::open_with(
+            dir.path(),
+            HashMapRepository::new(),
+            EventBus::default(),
+            10,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn lock_directory_while_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), COMPACT_AFTER).await;
+        let result = FileRepository::open_with(
+            dir.path(),
+            HashMapRepository::new(),
+            EventBus::default(),
+            10,
+        )
+        .await;
+        assert!(result.is_err());
+
+        drop(repository);
+        open(dir.path(), COMPACT_AFTER).await;
+    }
+
+    #[tokio::test]
+    async fn leave_data_alone_when_append_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), COMPACT_AFTER).await;
+        TodoRepository::create(&repository, CreateTodo::new("first".to_string()))
+            .await
+            .unwrap();
+        if let Log::File(log) = &mut *repository.log.lock().await {
+            log.file = tokio::fs::File::open(dir.path().join(LOG_FILE))
+                .await
+                .unwrap();
+        }
+
+        let completed = UpdateTodo {
+            text: None,
+            completed: Some(true),
+        };
+        assert!(TodoRepository::update(&repository, 1, completed)
+            .await
+            .is_err());
+        assert!(
+            !TodoRepository::find(&repository, 1)
+                .await
+                .unwrap()
+                .completed
+        );
+        assert!(repository.check().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn log_transaction_as_one_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = open(dir.path(), COMPACT_AFTER).await;
+        repository
+            .transaction(|tx| {
+                Box::pin(async move {
+                    fill(tx).await;
+                    Ok(())
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(1, repository.check().await.unwrap());
+        drop(repository);
+
+        assert_filled(&open(dir.path(), COMPACT_AFTER).await).await;
+    }
+}
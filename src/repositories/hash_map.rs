@@ -1,31 +1,341 @@
-#[cfg(test)]
-pub(crate) mod test_utils {
-    use std::collections::HashMap;
-    use std::sync::{Arc, RwLock};
-    use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::events::{EventBus, Publisher, TodoEvent};
+use crate::repositories::todos::Todo;
+use crate::repositories::users::User;
+use crate::repositories::webhooks::{Webhook, WebhookDelivery};
+use crate::repositories::{Clock, Transactional};
+
+/// Rows by id, in id order. Ids are never reused, like those of a Postgres sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Table<T> {
+    pub(crate) rows: BTreeMap<i32, T>,
+    last_id: i32,
+}
+
+impl<T> Table<T> {
+    pub(crate) fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    pub(crate) fn last_id(&self) -> i32 {
+        self.last_id
+    }
+
+    /// Puts back a row read from storage, keeping the ids handed out later clear of it.
+    pub(crate) fn restore(&mut self, id: i32, row: T) {
+        self.skip_to(id);
+        self.rows.insert(id, row);
+    }
+
+    /// Makes the ids up to `last_id` count as taken, e.g. those of rows since deleted.
+    pub(crate) fn skip_to(&mut self, last_id: i32) {
+        self.last_id = self.last_id.max(last_id);
+    }
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            rows: BTreeMap::new(),
+            last_id: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TodoData {
+    pub(crate) todos: Table<Todo>,
+    /// Deletions leave no row behind, so the list remembers when it last lost one.
+    pub(crate) last_deleted: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct WebhookData {
+    pub(crate) webhooks: Table<Webhook>,
+    pub(crate) deliveries: Table<WebhookDelivery>,
+}
+
+impl WebhookData {
+    /// Removes the webhook along with its deliveries, as the foreign key cascades in Postgres.
+    pub(crate) fn remove(&mut self, id: i32) {
+        self.webhooks.rows.remove(&id);
+        self.deliveries
+            .rows
+            .retain(|_, delivery| delivery.webhook_id != id);
+    }
+}
+
+/// A copy of all the data, taken at once.
+#[derive(Debug)]
+struct Data {
+    todos: TodoData,
+    users: Table<User>,
+    webhooks: WebhookData,
+}
+
+/// A copy of the data that a transaction works on, to replace the data with once it is done.
+#[derive(Debug)]
+pub(crate) struct Scratch {
+    pub(crate) repository: HashMapRepository,
+    start: Data,
+}
+
+/// Everything in process memory, for demos and ephemeral environments; nothing survives a
+/// restart and every instance has data of its own.
+///
+/// Like `SqliteRepository`, it publishes events on the `EventBus` directly.
+#[derive(Debug, Clone)]
+pub(crate) struct HashMapRepository {
+    store: Arc<RwLock<TodoData>>,
+    user_store: Arc<RwLock<Table<User>>>,
+    webhook_store: Arc<RwLock<WebhookData>>,
+    /// Taken by every write and held by transactions throughout, like the log of
+    /// `FileRepository`, so that writes wait for a transaction rather than conflict with it.
+    writer: Arc<Mutex<()>>,
+    events: Publisher,
+    clock: Clock,
+}
+
+impl HashMapRepository {
+    pub(crate) fn with_events(events: EventBus) -> Self {
+        HashMapRepository {
+            store: Arc::default(),
+            user_store: Arc::default(),
+            webhook_store: Arc::default(),
+            writer: Arc::default(),
+            events: Publisher::Bus(events),
+            clock: Clock::default(),
+        }
+    }
+
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
 
-    use crate::repositories::todos::Todo;
+    pub(crate) fn publish(&self, event: TodoEvent) {
+        self.events.publish(event);
+    }
 
-    type TodoData = HashMap<i32, Todo>;
+    /// Waits for the writes and the transaction in progress; see `writer`.
+    pub(crate) async fn writer(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().await
+    }
+
+    /// Whether this is the scratch of a transaction.
+    pub(crate) fn in_transaction(&self) -> bool {
+        matches!(self.events, Publisher::Deferred(_))
+    }
+
+    fn data(&self) -> Data {
+        let todos = self.read_store_ref();
+        let users = self.read_user_store_ref();
+        let webhooks = self.read_webhook_store_ref();
+        Data {
+            todos: todos.clone(),
+            users: users.clone(),
+            webhooks: webhooks.clone(),
+        }
+    }
 
-    #[derive(Debug, Clone)]
-    pub(crate) struct HashMapRepository {
-        store: Arc<RwLock<TodoData>>,
+    /// A copy of the data to work on, whose events are held back until it is committed.
+    pub(crate) fn scratch(&self) -> Scratch {
+        let start = self.data();
+        let repository = HashMapRepository {
+            store: Arc::new(RwLock::new(start.todos.clone())),
+            user_store: Arc::new(RwLock::new(start.users.clone())),
+            webhook_store: Arc::new(RwLock::new(start.webhooks.clone())),
+            writer: Arc::default(),
+            events: Publisher::deferred(),
+            clock: self.clock.clone(),
+        };
+        Scratch { repository, start }
     }
 
-    impl HashMapRepository {
-        pub(crate) fn new() -> Self {
-            HashMapRepository {
-                store: Arc::default(),
+    /// Replaces the tables the transaction of `scratch` changed, returning the events held
+    /// back for it.
+    ///
+    /// The caller holds the writer since the scratch was taken, so no other write is lost.
+    /// Tables the transaction did not change are left alone.
+    pub(crate) fn commit(&self, scratch: Scratch) -> Vec<TodoEvent> {
+        let mut todos = self.write_store_ref();
+        let mut users = self.write_user_store_ref();
+        let mut webhooks = self.write_webhook_store_ref();
+        let start = scratch.start;
+        let repository = scratch.repository;
+        let new_todos = std::mem::take(&mut *repository.write_store_ref());
+        let new_users = std::mem::take(&mut *repository.write_user_store_ref());
+        let new_webhooks = std::mem::take(&mut *repository.write_webhook_store_ref());
+        replace(&mut *todos, &start.todos, new_todos);
+        replace(&mut *users, &start.users, new_users);
+        replace(
+            &mut webhooks.webhooks,
+            &start.webhooks.webhooks,
+            new_webhooks.webhooks,
+        );
+        replace(
+            &mut webhooks.deliveries,
+            &start.webhooks.deliveries,
+            new_webhooks.deliveries,
+        );
+        repository.events.take()
+    }
+
+    /// Removes the user along with their webhooks, as the foreign key cascades in Postgres.
+    pub(crate) fn remove_user(&self, id: i32) {
+        let mut store = self.write_user_store_ref();
+        if store.rows.remove(&id).is_some() {
+            let mut webhook_store = self.write_webhook_store_ref();
+            let webhooks: Vec<i32> = webhook_store
+                .webhooks
+                .rows
+                .values()
+                .filter(|webhook| webhook.user_id == id)
+                .map(|webhook| webhook.id)
+                .collect();
+            for webhook in webhooks {
+                webhook_store.remove(webhook);
             }
         }
+    }
+
+    pub(crate) fn write_store_ref(&self) -> RwLockWriteGuard<'_, TodoData> {
+        self.store.write().unwrap()
+    }
+
+    pub(crate) fn read_store_ref(&self) -> RwLockReadGuard<'_, TodoData> {
+        self.store.read().unwrap()
+    }
+
+    pub(crate) fn write_user_store_ref(&self) -> RwLockWriteGuard<'_, Table<User>> {
+        self.user_store.write().unwrap()
+    }
+
+    pub(crate) fn read_user_store_ref(&self) -> RwLockReadGuard<'_, Table<User>> {
+        self.user_store.read().unwrap()
+    }
 
-        pub(crate) fn write_store_ref(&self) -> RwLockWriteGuard<TodoData> {
-            self.store.write().unwrap()
+    pub(crate) fn write_webhook_store_ref(&self) -> RwLockWriteGuard<'_, WebhookData> {
+        self.webhook_store.write().unwrap()
+    }
+
+    pub(crate) fn read_webhook_store_ref(&self) -> RwLockReadGuard<'_, WebhookData> {
+        self.webhook_store.read().unwrap()
+    }
+}
+
+/// Puts the `new` table of a transaction in place, if the transaction changed it.
+fn replace<T: PartialEq>(current: &mut T, start: &T, new: T) {
+    if new != *start {
+        *current = new;
+    }
+}
+
+#[async_trait]
+impl Transactional for HashMapRepository {
+    async fn transaction<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'r> FnOnce(&'r Self) -> BoxFuture<'r, anyhow::Result<T>> + Send + 'static,
+    {
+        if self.in_transaction() {
+            return work(self).await;
+        }
+        let _writer = self.writer().await;
+        let scratch = self.scratch();
+        let value = work(&scratch.repository).await?;
+        for event in self.commit(scratch) {
+            self.publish(event);
         }
+        Ok(value)
+    }
+}
 
-        pub(crate) fn read_store_ref(&self) -> RwLockReadGuard<TodoData> {
-            self.store.read().unwrap()
+#[cfg(test)]
+impl HashMapRepository {
+    /// A repository whose clock stands at `start_time()`.
+    pub(crate) fn new() -> Self {
+        Self {
+            clock: Clock::stopped_at(test_utils::start_time()),
+            ..Self::with_events(EventBus::default())
         }
     }
+
+    pub(crate) fn set_now(&self, now: DateTime<Utc>) {
+        self.clock.set(now);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    /// Where the clock of repositories under test starts, so that timestamps are predictable.
+    pub(crate) fn start_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::TodoEventKind;
+    use crate::repositories::todos::{CreateTodo, TodoRepository};
+    use crate::repositories::webhooks::{CreateWebhookDelivery, WebhookRepository};
+
+    fn delivery() -> CreateWebhookDelivery {
+        CreateWebhookDelivery {
+            webhook_id: 1,
+            event: TodoEventKind::Created,
+            payload: "{}".to_string(),
+            attempt: 1,
+            status_code: Some(204),
+            error: None,
+            succeeded: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn commit_alongside_changes_to_other_tables() {
+        let repository = HashMapRepository::new();
+        let scratch = repository.scratch();
+        TodoRepository::create(&scratch.repository, CreateTodo::new("todo".to_string()))
+            .await
+            .unwrap();
+        repository.record_delivery(delivery()).await.unwrap();
+
+        repository.commit(scratch);
+        assert_eq!(1, repository.read_store_ref().todos.rows.len());
+        assert_eq!(1, repository.read_webhook_store_ref().deliveries.rows.len());
+    }
+
+    #[tokio::test]
+    async fn make_writes_wait_for_transaction() {
+        let repository = HashMapRepository::new();
+        let transaction = repository.transaction(|tx| {
+            Box::pin(async move {
+                TodoRepository::create(tx, CreateTodo::new("mine".to_string())).await?;
+                // lets the write below run, which waits instead of changing the todos under us
+                tokio::task::yield_now().await;
+                Ok(())
+            })
+        });
+        let write = TodoRepository::create(&repository, CreateTodo::new("theirs".to_string()));
+        let (committed, written) = tokio::join!(transaction, write);
+        committed.unwrap();
+        written.unwrap();
+
+        let todos = TodoRepository::all(&repository).await.unwrap();
+        assert_eq!(
+            vec!["theirs", "mine"],
+            todos.iter().map(|todo| &todo.text).collect::<Vec<_>>()
+        );
+    }
 }
@@ -1,12 +1,294 @@
-use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use anyhow::Context;
+use axum::async_trait;
+use futures_util::future::BoxFuture;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, PgConnection, PgPool, Postgres};
+use tokio::time::Instant;
+
+use crate::config::DatabaseConfig;
+use crate::consistency;
+use crate::repositories::{begin, finish, DbConnection, SharedTransaction, Transactional};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// How long a read waits for a replica before it goes to the primary instead.
+const REPLICA_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a replica that failed to give a connection is left out.
+const REPLICA_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Everything in Postgres. Writes, and the reads of users and webhooks, go to the primary
+/// `pool`; todo reads are spread over the replicas, if any.
 #[derive(Debug, Clone)]
 pub(crate) struct PostgresRepository {
     pub(crate) pool: PgPool,
+    replicas: Replicas,
+    tx: Option<SharedTransaction<Postgres>>,
+}
+
+/// The read replicas, taken in turn.
+#[derive(Debug, Clone, Default)]
+struct Replicas {
+    replicas: Arc<[Replica]>,
+    next: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct Replica {
+    pool: PgPool,
+    /// Until when the replica is left out, after it failed to give a connection.
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Replicas {
+    /// The next replica in turn that is not left out.
+    fn next(&self) -> Option<&Replica> {
+        let count = self.replicas.len();
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        (0..count)
+            .map(|offset| &self.replicas[(next + offset) % count])
+            .find(|replica| replica.is_up(now))
+    }
+}
+
+impl Replica {
+    fn is_up(&self, now: Instant) -> bool {
+        self.down_until
+            .lock()
+            .unwrap()
+            .is_none_or(|down_until| now >= down_until)
+    }
+
+    fn mark_down(&self) {
+        *self.down_until.lock().unwrap() = Some(Instant::now() + REPLICA_COOLDOWN);
+    }
 }
 
 impl PostgresRepository {
     pub(crate) fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            replicas: Replicas::default(),
+            tx: None,
+        }
+    }
+
+    pub(crate) fn with_replicas(self, replicas: Vec<PgPool>) -> Self {
+        let replicas = Replicas {
+            replicas: replicas
+                .into_iter()
+                .map(|pool| Replica {
+                    pool,
+                    down_until: Mutex::new(None),
+                })
+                .collect(),
+            next: Arc::default(),
+        };
+        Self { replicas, ..self }
+    }
+
+    pub(crate) async fn connection(&self) -> anyhow::Result<DbConnection<'_, Postgres>> {
+        DbConnection::acquire(&self.pool, &self.tx).await
+    }
+
+    /// A connection for a read that may lag behind the primary.
+    ///
+    /// Transactions, and the requests of clients that wrote recently, read from the primary;
+    /// so does everyone while no replica is available. A replica that fails to give a
+    /// connection is left out for `REPLICA_COOLDOWN`.
+    pub(crate) async fn read_connection(&self) -> anyhow::Result<DbConnection<'_, Postgres>> {
+        if self.tx.is_none() && !consistency::reads_from_primary() {
+            if let Some(replica) = self.replicas.next() {
+                match replica.pool.acquire().await {
+                    Ok(connection) => return Ok(DbConnection::Pool(connection)),
+                    Err(e) => {
+                        tracing::warn!(
+                            "leaving a replica out for {:?}, reading from the primary: {}",
+                            REPLICA_COOLDOWN,
+                            e
+                        );
+                        replica.mark_down();
+                    }
+                }
+            }
+        }
+        self.connection().await
+    }
+}
+
+#[async_trait]
+impl Transactional for PostgresRepository {
+    async fn transaction<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'r> FnOnce(&'r Self) -> BoxFuture<'r, anyhow::Result<T>> + Send + 'static,
+    {
+        if self.tx.is_some() {
+            return work(self).await;
+        }
+        let tx = begin(&self.pool).await?;
+        let repository = Self {
+            tx: Some(Arc::clone(&tx)),
+            ..self.clone()
+        };
+        let result = work(&repository).await;
+        drop(repository);
+        finish(tx, result).await
+    }
+}
+
+/// Connects to the database, retrying with backoff for up to the startup timeout so that
+/// the app can start alongside the database, e.g. with docker compose.
+pub(crate) async fn connect(config: &DatabaseConfig) -> anyhow::Result<PgPool> {
+    tracing::debug!("start connecting to the database...");
+    let options = connect_options(config)?;
+    let pool_options = pool_options(config).acquire_timeout(config.acquire_timeout());
+    let context = || {
+        format!(
+            "failed to connect to the database whose url is: [{}]",
+            config.redacted_url()
+        )
+    };
+    // a single connection fails fast with the actual error, where the pool would time out
+    let deadline = Instant::now() + config.startup_timeout();
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match PgConnection::connect_with(&options).await {
+            Ok(connection) => {
+                let _ = connection.close().await;
+                break;
+            }
+            Err(e) if Instant::now() + backoff < deadline => {
+                tracing::warn!(
+                    "the database is not available yet, retrying in {:?}: {}",
+                    backoff,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => return Err(e).with_context(context),
+        }
+    }
+    pool_options
+        .connect_with(options)
+        .await
+        .with_context(context)
+}
+
+/// A pool for the read replica of `config`, which connects when it is first used: a replica
+/// that is down neither holds up the startup nor a read for longer than
+/// `REPLICA_ACQUIRE_TIMEOUT`.
+pub(crate) fn connect_replica(config: &DatabaseConfig) -> anyhow::Result<PgPool> {
+    let acquire_timeout = config.acquire_timeout().min(REPLICA_ACQUIRE_TIMEOUT);
+    Ok(pool_options(config)
+        .acquire_timeout(acquire_timeout)
+        .connect_lazy_with(connect_options(config)?))
+}
+
+fn connect_options(config: &DatabaseConfig) -> anyhow::Result<PgConnectOptions> {
+    Ok(PgConnectOptions::from_str(&config.url)
+        .with_context(|| format!("invalid database url: [{}]", config.redacted_url()))?
+        .options([(
+            "statement_timeout",
+            format!("{}s", config.statement_timeout),
+        )]))
+}
+
+fn pool_options(config: &DatabaseConfig) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .idle_timeout(config.idle_timeout())
+}
+
+/// The migrations under `migrations/`, embedded at build time.
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Names the server the Postgres tests create their databases on, e.g. the one of
+    /// `docker-compose.yml`. The tests are ignored unless run with `--include-ignored`.
+    const TEST_DATABASE_URL: &str = "TEST_DATABASE_URL";
+
+    fn server_url() -> String {
+        std::env::var(TEST_DATABASE_URL).unwrap_or_else(|_| {
+            panic!(
+                "{} must name a Postgres server to run the Postgres tests",
+                TEST_DATABASE_URL
+            )
+        })
+    }
+
+    /// A migrated database of the test's own on the server of `TEST_DATABASE_URL`.
+    pub(crate) async fn database() -> PgPool {
+        static DATABASES: AtomicUsize = AtomicUsize::new(0);
+        let options = PgConnectOptions::from_str(&server_url()).unwrap();
+        let name = format!(
+            "todos_test_{}_{}",
+            std::process::id(),
+            DATABASES.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut connection = PgConnection::connect_with(&options).await.unwrap();
+        sqlx::query(&format!(r#"CREATE DATABASE "{}""#, name))
+            .execute(&mut connection)
+            .await
+            .unwrap();
+        connection.close().await.unwrap();
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options.database(&name))
+            .await
+            .unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        pool
+    }
+
+    /// Drops the database of `pool` once the test using it passed; a failed test leaves it
+    /// behind to look into.
+    pub(crate) async fn drop_database(pool: PgPool) {
+        let name = pool.connect_options().get_database().unwrap().to_string();
+        pool.close().await;
+        let mut connection = PgConnection::connect(&server_url()).await.unwrap();
+        // the server may not have seen the closed connections go yet
+        sqlx::query(&format!(r#"DROP DATABASE "{}" WITH (FORCE)"#, name))
+            .execute(&mut connection)
+            .await
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+    async fn leave_out_replica_that_is_down() {
+        let pool = test_utils::database().await;
+        let config = DatabaseConfig {
+            url: "postgres://todos@127.0.0.1:1/todos".to_string(),
+            ..DatabaseConfig::default()
+        };
+        let replica = connect_replica(&config).unwrap();
+        let repository = PostgresRepository::new(pool.clone()).with_replicas(vec![replica]);
+
+        let started = Instant::now();
+        repository.read_connection().await.unwrap();
+        assert!(started.elapsed() <= REPLICA_ACQUIRE_TIMEOUT * 2);
+        assert!(repository.replicas.next().is_none());
+        repository.read_connection().await.unwrap();
+
+        test_utils::drop_database(pool).await;
     }
 }
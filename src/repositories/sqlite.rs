@@ -0,0 +1,274 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::async_trait;
+use futures_util::future::BoxFuture;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+
+use crate::config::DatabaseConfig;
+use crate::events::{EventBus, Publisher, TodoEvent};
+use crate::repositories::{begin, finish, Clock, DbConnection, SharedTransaction, Transactional};
+
+/// Everything in one SQLite database, for installs that run a single instance.
+///
+/// There is no outbox: events are published on this instance's `EventBus` once the change
+/// is committed.
+#[derive(Debug, Clone)]
+pub(crate) struct SqliteRepository {
+    pub(crate) pool: SqlitePool,
+    tx: Option<SharedTransaction<Sqlite>>,
+    events: Publisher,
+    clock: Clock,
+}
+
+impl SqliteRepository {
+    pub(crate) fn new(pool: SqlitePool, events: EventBus) -> Self {
+        Self {
+            pool,
+            tx: None,
+            events: Publisher::Bus(events),
+            clock: Clock::default(),
+        }
+    }
+
+    pub(crate) async fn connection(&self) -> anyhow::Result<DbConnection<'_, Sqlite>> {
+        DbConnection::acquire(&self.pool, &self.tx).await
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_clock(self, clock: Clock) -> Self {
+        Self { clock, ..self }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_now(&self, now: chrono::DateTime<chrono::Utc>) {
+        self.clock.set(now);
+    }
+
+    pub(crate) fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    pub(crate) fn publish(&self, event: TodoEvent) {
+        self.events.publish(event);
+    }
+}
+
+#[async_trait]
+impl Transactional for SqliteRepository {
+    async fn transaction<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'r> FnOnce(&'r Self) -> BoxFuture<'r, anyhow::Result<T>> + Send + 'static,
+    {
+        if self.tx.is_some() {
+            return work(self).await;
+        }
+        let tx = begin(&self.pool).await?;
+        lock_for_writing(&mut *tx.lock().await).await?;
+        let repository = Self {
+            pool: self.pool.clone(),
+            tx: Some(Arc::clone(&tx)),
+            events: Publisher::deferred(),
+            clock: self.clock.clone(),
+        };
+        let result = work(&repository).await;
+        let events = repository.events.take();
+        drop(repository);
+        let value = finish(tx, result).await?;
+        for event in events {
+            self.publish(event);
+        }
+        Ok(value)
+    }
+}
+
+/// Takes the write lock for the transaction open on `conn`, as `BEGIN IMMEDIATE` would, which
+/// sqlx cannot issue.
+///
+/// A transaction that reads first fails with `SQLITE_BUSY` at its first write once another
+/// connection committed in between, whatever the busy timeout; holding the lock from the start
+/// makes it wait for the other writer instead.
+pub(crate) async fn lock_for_writing(conn: &mut SqliteConnection) -> anyhow::Result<()> {
+    // a write takes the lock even when it changes nothing
+    sqlx::query(
+        r#"
+        UPDATE todo_list SET id = id WHERE false
+        "#,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Opens the database, creating the file if needed, and applies the pending migrations.
+///
+/// Nothing else shares the database, so there is no reason to wait for an operator to
+/// migrate it.
+pub(crate) async fn connect(config: &DatabaseConfig) -> anyhow::Result<SqlitePool> {
+    tracing::debug!("start opening the database...");
+    let options = SqliteConnectOptions::from_str(&config.url)
+        .with_context(|| format!("invalid database url: [{}]", config.redacted_url()))?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .busy_timeout(Duration::from_secs(5));
+    let pool_options = if config.url.contains(":memory:") {
+        // every connection would open a database of its own, which must outlive idle periods
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .idle_timeout(config.idle_timeout())
+    };
+    let pool = pool_options
+        .acquire_timeout(config.acquire_timeout())
+        .connect_with(options)
+        .await
+        .with_context(|| format!("failed to open the database [{}]", config.redacted_url()))?;
+    MIGRATOR
+        .run(&pool)
+        .await
+        .context("failed to apply the migrations")?;
+    Ok(pool)
+}
+
+/// The migrations under `migrations/sqlite/`, embedded at build time.
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+    use crate::repositories::hash_map::test_utils::start_time;
+
+    /// A repository on a fresh in-memory database, whose clock stands at `start_time()`.
+    pub(crate) async fn in_memory() -> SqliteRepository {
+        let config = DatabaseConfig {
+            url: "sqlite::memory:".to_string(),
+            ..DatabaseConfig::default()
+        };
+        let pool = connect(&config).await.unwrap();
+        SqliteRepository::new(pool, EventBus::default()).with_clock(Clock::stopped_at(start_time()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::in_memory;
+    use super::*;
+    use crate::events::TodoEventKind;
+    use crate::repositories::todos::{CreateTodo, TodoRepository, UpdateTodo};
+    use crate::repositories::users::{CreateUser, UserRepository};
+    use crate::repositories::webhooks::{CreateWebhook, Webhook, WebhookRepository};
+
+    #[tokio::test]
+    async fn subscribe_webhooks_to_event_kinds() {
+        let repository = in_memory().await;
+        let payload = CreateUser {
+            username: "user".to_string(),
+            email: "user@example.com".to_string(),
+            password_hash: "hash".to_string(),
+        };
+        let user = UserRepository::create(&repository, payload).await.unwrap();
+        for events in [vec![], vec![TodoEventKind::Completed]] {
+            let payload = CreateWebhook::new(
+                "http://localhost/hook".to_string(),
+                "0123456789abcdef".to_string(),
+                events,
+            );
+            WebhookRepository::create(&repository, user.id, payload)
+                .await
+                .unwrap();
+        }
+        let ids = |webhooks: Vec<Webhook>| {
+            webhooks
+                .into_iter()
+                .map(|webhook| webhook.id)
+                .collect::<Vec<_>>()
+        };
+        let created = repository
+            .subscribed_to(TodoEventKind::Created)
+            .await
+            .unwrap();
+        assert_eq!(vec![1], ids(created));
+        let completed = repository
+            .subscribed_to(TodoEventKind::Completed)
+            .await
+            .unwrap();
+        assert_eq!(vec![1, 2], ids(completed));
+
+        UserRepository::delete(&repository, user.id).await.unwrap();
+        assert!(WebhookRepository::all(&repository, user.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn publish_events_once_committed() {
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        let repository = SqliteRepository::new(in_memory().await.pool, events);
+        let result: anyhow::Result<()> = repository
+            .transaction(|tx| {
+                Box::pin(async move {
+                    TodoRepository::create(tx, CreateTodo::new("rolled back".to_string())).await?;
+                    anyhow::bail!("failed")
+                })
+            })
+            .await;
+        assert!(result.is_err());
+        assert!(receiver.try_recv().is_err());
+
+        repository
+            .transaction(|tx| {
+                Box::pin(async move {
+                    TodoRepository::create(tx, CreateTodo::new("committed".to_string())).await?;
+                    Ok(())
+                })
+            })
+            .await
+            .unwrap();
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            (TodoEventKind::Created, "committed"),
+            (event.kind, event.todo.text.as_str())
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn update_concurrently_on_several_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DatabaseConfig {
+            url: format!("sqlite://{}", dir.path().join("todos.db").display()),
+            max_connections: 4,
+            ..DatabaseConfig::default()
+        };
+        let repository =
+            SqliteRepository::new(connect(&config).await.unwrap(), EventBus::default());
+        let todo = TodoRepository::create(&repository, CreateTodo::new("todo".to_string()))
+            .await
+            .unwrap();
+        let updates = (0..20).map(|i| {
+            let repository = repository.clone();
+            tokio::spawn(async move {
+                let payload = UpdateTodo {
+                    text: Some(format!("todo {}", i)),
+                    completed: None,
+                };
+                TodoRepository::update(&repository, todo.id, payload).await
+            })
+        });
+        for update in futures_util::future::join_all(updates).await {
+            update.unwrap().unwrap();
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use axum::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use thiserror::Error;
 use validator::Validate;
 
+pub(crate) mod cached;
+mod event_sourced;
+mod file;
 mod hash_map;
+pub(crate) mod instrumented;
 mod postgres;
+mod sqlite;
 
 #[async_trait]
 pub(crate) trait TodoRepository: Clone + Send + Sync + 'static {
@@ -13,6 +20,43 @@ pub(crate) trait TodoRepository: Clone + Send + Sync + 'static {
     async fn all(&self) -> anyhow::Result<Vec<Todo>>;
     async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo>;
     async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    async fn stats(&self) -> anyhow::Result<TodoStats>;
+    /// When a todo was last created, updated or deleted; `None` before the first change.
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>>;
+    /// The events that made up the todo, oldest first, on stores that keep them.
+    async fn history(&self, id: i32) -> anyhow::Result<Vec<StoredEvent>> {
+        let _ = id;
+        Err(NoHistory.into())
+    }
+}
+
+/// The store keeps todos as they are now, not the events that made them.
+#[derive(Debug, Error)]
+#[error("the todo store keeps no history")]
+pub(crate) struct NoHistory;
+
+/// What happened to a todo.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Change {
+    TodoCreated { text: String },
+    TextChanged { text: String },
+    Completed,
+    Reopened,
+    Deleted,
+}
+
+/// An event in the history of a todo, which is never changed once recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredEvent {
+    /// Where the event is in the stream of every todo, from 1.
+    pub(crate) position: u64,
+    pub(crate) todo_id: i32,
+    /// Where the event is in the stream of its todo, from 1.
+    pub(crate) version: u32,
+    pub(crate) at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub(crate) change: Change,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
@@ -20,6 +64,14 @@ pub(crate) struct Todo {
     pub(crate) id: i32,
     pub(crate) text: String,
     pub(crate) completed: bool,
+    #[serde(default)]
+    pub(crate) updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct TodoStats {
+    pub(crate) total: i64,
+    pub(crate) open: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
@@ -29,7 +81,6 @@ pub(crate) struct CreateTodo {
     text: String,
 }
 
-#[cfg(test)]
 impl CreateTodo {
     pub(crate) fn new(text: String) -> Self {
         Self { text }
@@ -40,8 +91,8 @@ impl CreateTodo {
 pub(crate) struct UpdateTodo {
     #[validate(length(min = 1, message = "text must not be empty"))]
     #[validate(length(max = 100, message = "text length exceeds the limit"))]
-    text: Option<String>,
-    completed: Option<bool>,
+    pub(crate) text: Option<String>,
+    pub(crate) completed: Option<bool>,
 }
 
 #[cfg(test)]
@@ -51,6 +102,7 @@ impl Todo {
             id,
             text,
             completed: false,
+            updated_at: crate::repositories::hash_map::test_utils::start_time(),
         }
     }
 }
@@ -0,0 +1,490 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use hashlink::LruCache;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::config::CacheConfig;
+use crate::consistency;
+use crate::events::EventBus;
+use crate::metrics::Metrics;
+use crate::repositories::todos::{
+    CreateTodo, StoredEvent, Todo, TodoRepository, TodoStats, UpdateTodo,
+};
+use crate::repositories::users::{self, CreateUser, UpdateUser, User};
+use crate::repositories::Transactional;
+
+/// The lookup a cache entry holds the result of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Find(i32),
+    All,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Todo(Todo),
+    Todos(Vec<Todo>),
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+struct Cache {
+    entries: LruCache<Key, Entry>,
+    /// Bumped by every invalidation, so that a lookup which read before it does not store
+    /// what it read.
+    generation: u64,
+    /// How far the reads of the inner repository may lag behind its writes.
+    replica_lag: Duration,
+    /// Until when reads may still miss the last invalidated change, and are not stored.
+    settles_at: Option<Instant>,
+}
+
+/// Decorates a `TodoRepository` with a bounded cache of `find` and `all` results.
+///
+/// Writes through the decorator drop the entries they change, and `invalidate_on` does the
+/// same for the events of every instance. Entries expire after the configured ttl in case an
+/// event is lost.
+#[derive(Clone)]
+pub(crate) struct CachedRepository<R> {
+    inner: R,
+    cache: Arc<Mutex<Cache>>,
+    ttl: Duration,
+    metrics: Metrics,
+}
+
+impl<R> CachedRepository<R> {
+    pub(crate) fn new(inner: R, config: &CacheConfig, metrics: Metrics) -> Self {
+        let cache = Cache {
+            entries: LruCache::new(config.capacity),
+            generation: 0,
+            replica_lag: Duration::ZERO,
+            settles_at: None,
+        };
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(cache)),
+            ttl: config.ttl(),
+            metrics,
+        }
+    }
+
+    /// Keeps what the inner repository reads within `lag` of a change out of the cache, as it
+    /// may come from a replica the change has not reached yet.
+    pub(crate) fn with_replica_lag(self, lag: Duration) -> Self {
+        self.cache.lock().unwrap().replica_lag = lag;
+        self
+    }
+
+    /// Drops the entries the todo events published on `events` change, until the bus is
+    /// closed; on Postgres, these include the changes made by other instances.
+    pub(crate) fn invalidate_on(&self, events: &EventBus) -> impl Future<Output = ()> + Send {
+        let cache = Arc::clone(&self.cache);
+        let mut receiver = events.subscribe();
+        let closed = events.closed();
+        async move {
+            tokio::pin!(closed);
+            loop {
+                let event = tokio::select! {
+                    _ = &mut closed => return,
+                    event = receiver.recv() => event,
+                };
+                let mut cache = cache.lock().unwrap();
+                match event {
+                    Ok(event) => cache.invalidate(&[Key::Find(event.todo.id), Key::All]),
+                    // what the missed events changed is unknown
+                    Err(RecvError::Lagged(_)) => cache.clear(),
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        }
+    }
+
+    /// Whether lookups go through the cache; not for requests that must read from the
+    /// primary, as the cache may hold what a replica read before their write.
+    fn enabled(&self) -> bool {
+        !consistency::reads_from_primary() && self.cache.lock().unwrap().entries.capacity() > 0
+    }
+
+    /// The cached value for `key`, or the generation to store the value read instead under.
+    fn lookup(&self, operation: &str, key: Key) -> Result<Value, u64> {
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        let value = match cache.entries.get(&key) {
+            Some(entry) if entry.expires_at > now => Some(entry.value.clone()),
+            _ => None,
+        };
+        self.metrics.record_cache_lookup(operation, value.is_some());
+        value.ok_or(cache.generation)
+    }
+
+    fn store(&self, key: Key, value: Value, generation: u64) {
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        let settled = cache.settles_at.is_none_or(|settles_at| now >= settles_at);
+        if cache.generation == generation && settled {
+            let expires_at = now + self.ttl;
+            cache.entries.insert(key, Entry { value, expires_at });
+        }
+    }
+
+    fn invalidate(&self, id: i32) {
+        self.cache
+            .lock()
+            .unwrap()
+            .invalidate(&[Key::Find(id), Key::All]);
+    }
+}
+
+impl Cache {
+    fn invalidate(&mut self, keys: &[Key]) {
+        self.changed();
+        for key in keys {
+            self.entries.remove(key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.changed();
+        self.entries.clear();
+    }
+
+    fn changed(&mut self) {
+        self.generation += 1;
+        if !self.replica_lag.is_zero() {
+            self.settles_at = Some(Instant::now() + self.replica_lag);
+        }
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for CachedRepository<R> {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let result = self.inner.create(payload).await;
+        if let Ok(todo) = &result {
+            self.invalidate(todo.id);
+        }
+        result
+    }
+
+    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        if !self.enabled() {
+            return self.inner.find(id).await;
+        }
+        let generation = match self.lookup("find", Key::Find(id)) {
+            Ok(Value::Todo(todo)) => return Ok(todo),
+            Ok(Value::Todos(_)) => unreachable!("a find entry holds a todo"),
+            Err(generation) => generation,
+        };
+        let todo = self.inner.find(id).await?;
+        self.store(Key::Find(id), Value::Todo(todo.clone()), generation);
+        Ok(todo)
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        if !self.enabled() {
+            return self.inner.all().await;
+        }
+        let generation = match self.lookup("all", Key::All) {
+            Ok(Value::Todos(todos)) => return Ok(todos),
+            Ok(Value::Todo(_)) => unreachable!("the all entry holds todos"),
+            Err(generation) => generation,
+        };
+        let todos = self.inner.all().await?;
+        self.store(Key::All, Value::Todos(todos.clone()), generation);
+        Ok(todos)
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        // even a failed write may have been applied
+        let result = self.inner.update(id, payload).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let result = self.inner.delete(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        self.inner.stats().await
+    }
+
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.inner.last_modified().await
+    }
+
+    async fn history(&self, id: i32) -> anyhow::Result<Vec<StoredEvent>> {
+        self.inner.history(id).await
+    }
+}
+
+/// Users are not cached; the decorator only passes them on, so that it can run transactions.
+#[async_trait]
+impl<R: users::UserRepository + Send + Sync> users::UserRepository for CachedRepository<R> {
+    async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+        self.inner.create(payload).await
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+        self.inner.find_by_email(email).await
+    }
+
+    async fn find_by_id(&self, id: i32) -> anyhow::Result<User> {
+        self.inner.find_by_id(id).await
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        self.inner.all().await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateUser) -> anyhow::Result<User> {
+        self.inner.update(id, payload).await
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        self.inner.delete(id).await
+    }
+}
+
+#[async_trait]
+impl<R: Transactional> Transactional for CachedRepository<R> {
+    /// Runs `work` in a transaction of the inner repository, bypassing the cache, which is
+    /// cleared afterwards as the todos the transaction changed are not tracked.
+    async fn transaction<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: for<'r> FnOnce(&'r Self) -> BoxFuture<'r, anyhow::Result<T>> + Send + 'static,
+    {
+        let metrics = self.metrics.clone();
+        let result = self
+            .inner
+            .transaction(move |inner| {
+                let uncached = CacheConfig {
+                    capacity: 0,
+                    ..CacheConfig::default()
+                };
+                let repository = CachedRepository::new(inner.clone(), &uncached, metrics);
+                Box::pin(async move { work(&repository).await })
+            })
+            .await;
+        // even a failed commit may have been applied
+        self.cache.lock().unwrap().clear();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{TodoEvent, TodoEventKind};
+    use crate::repositories::hash_map::HashMapRepository;
+
+    fn cached(capacity: usize) -> (HashMapRepository, CachedRepository<HashMapRepository>) {
+        let inner = HashMapRepository::new();
+        let config = CacheConfig { capacity, ttl: 60 };
+        let repository = CachedRepository::new(inner.clone(), &config, Metrics::new());
+        (inner, repository)
+    }
+
+    /// Changes the todo behind the back of the cache, as another instance would.
+    async fn rename(inner: &HashMapRepository, id: i32, text: &str) -> Todo {
+        let payload = UpdateTodo {
+            text: Some(text.to_string()),
+            completed: None,
+        };
+        inner.update(id, payload).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_lookups_from_cache() {
+        let (inner, repository) = cached(10);
+        let todo = repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(todo, repository.find(1).await.unwrap());
+        assert_eq!(vec![todo.clone()], repository.all().await.unwrap());
+
+        rename(&inner, 1, "renamed").await;
+        assert_eq!(todo, repository.find(1).await.unwrap());
+        assert_eq!(vec![todo], repository.all().await.unwrap());
+        let metrics = repository.metrics.encode().unwrap();
+        assert!(metrics.contains(r#"todo_cache_lookups_total{operation="find",result="hit"} 1"#));
+        assert!(metrics.contains(r#"todo_cache_lookups_total{operation="all",result="miss"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn invalidate_on_writes() {
+        let (_, repository) = cached(10);
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+        repository.all().await.unwrap();
+
+        let payload = UpdateTodo {
+            text: None,
+            completed: Some(true),
+        };
+        let updated = repository.update(1, payload).await.unwrap();
+        assert_eq!(updated, repository.find(1).await.unwrap());
+        assert_eq!(vec![updated], repository.all().await.unwrap());
+        repository.delete(1).await.unwrap();
+        assert!(repository.find(1).await.is_err());
+        assert!(repository.all().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalidate_on_events() {
+        let (inner, repository) = cached(10);
+        let events = EventBus::default();
+        tokio::spawn(repository.invalidate_on(&events));
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+
+        let todo = rename(&inner, 1, "renamed").await;
+        events.publish(TodoEvent {
+            kind: TodoEventKind::Updated,
+            todo: todo.clone(),
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(todo, repository.find(1).await.unwrap());
+        events.close();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expire_entries_after_ttl() {
+        let (inner, repository) = cached(10);
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+        let todo = rename(&inner, 1, "renamed").await;
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(todo, repository.find(1).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used() {
+        let (inner, repository) = cached(2);
+        for text in ["first", "second", "third"] {
+            repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+        }
+        repository.find(1).await.unwrap();
+        repository.find(2).await.unwrap();
+        repository.find(1).await.unwrap();
+        repository.find(3).await.unwrap();
+
+        let renamed = rename(&inner, 2, "renamed").await;
+        rename(&inner, 3, "renamed").await;
+        assert_eq!(renamed, repository.find(2).await.unwrap());
+        assert_eq!("third", repository.find(3).await.unwrap().text);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skip_storing_while_replicas_catch_up() {
+        let (inner, repository) = cached(10);
+        let repository = repository.with_replica_lag(Duration::from_secs(5));
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+        let todo = rename(&inner, 1, "renamed").await;
+        assert_eq!(todo, repository.find(1).await.unwrap());
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        repository.find(1).await.unwrap();
+        rename(&inner, 1, "renamed again").await;
+        assert_eq!(todo, repository.find(1).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pass_through_when_reading_from_primary() {
+        let (inner, repository) = cached(10);
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+        repository.all().await.unwrap();
+
+        let todo = rename(&inner, 1, "renamed").await;
+        let (found, all) = consistency::read_from_primary(async {
+            (repository.find(1).await, repository.all().await)
+        })
+        .await;
+        assert_eq!(todo, found.unwrap());
+        assert_eq!(vec![todo], all.unwrap());
+    }
+
+    #[tokio::test]
+    async fn bypass_cache_in_transaction_and_clear_it_on_commit() {
+        let (_, repository) = cached(10);
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+        fn change(
+            tx: &CachedRepository<HashMapRepository>,
+            fail: bool,
+        ) -> BoxFuture<'_, anyhow::Result<()>> {
+            Box::pin(async move {
+                let payload = UpdateTodo {
+                    text: Some("changed".to_string()),
+                    completed: None,
+                };
+                TodoRepository::update(tx, 1, payload).await?;
+                assert_eq!("changed", TodoRepository::find(tx, 1).await?.text);
+                anyhow::ensure!(!fail, "failed");
+                Ok(())
+            })
+        }
+
+        // what the transaction read is not cached, as it may be rolled back
+        assert!(repository.transaction(|tx| change(tx, true)).await.is_err());
+        assert_eq!("text", repository.find(1).await.unwrap().text);
+
+        repository
+            .transaction(|tx| change(tx, false))
+            .await
+            .unwrap();
+        assert_eq!("changed", repository.find(1).await.unwrap().text);
+    }
+
+    #[tokio::test]
+    async fn pass_through_when_disabled() {
+        let (inner, repository) = cached(0);
+        repository
+            .create(CreateTodo::new("text".to_string()))
+            .await
+            .unwrap();
+        repository.find(1).await.unwrap();
+
+        let todo = rename(&inner, 1, "renamed").await;
+        assert_eq!(todo, repository.find(1).await.unwrap());
+    }
+}
@@ -0,0 +1,96 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::events::{TodoEvent, TodoEventKind};
+use crate::repositories::event_sourced::EventSourcedRepository;
+use crate::repositories::todos::{
+    Change, CreateTodo, StoredEvent, Todo, TodoRepository, TodoStats, UpdateTodo,
+};
+
+#[async_trait]
+impl TodoRepository for EventSourcedRepository {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut log = self.log().await?;
+        let id = self.read_projection().last_id() + 1;
+        let changes = vec![Change::TodoCreated { text: payload.text }];
+        let todo = self
+            .record(&mut log, id, changes)
+            .await?
+            .expect("a created todo is kept");
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
+
+    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        self.read_projection().find(id)
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        Ok(self.read_projection().todos().cloned().collect())
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let mut log = self.log().await?;
+        let old_todo = self.read_projection().find(id)?;
+        let mut changes = Vec::new();
+        match payload.text {
+            Some(text) if text != old_todo.text => changes.push(Change::TextChanged { text }),
+            _ => {}
+        }
+        match payload.completed {
+            Some(true) if !old_todo.completed => changes.push(Change::Completed),
+            Some(false) if old_todo.completed => changes.push(Change::Reopened),
+            _ => {}
+        }
+        // nothing happened, so there is nothing to record
+        if changes.is_empty() {
+            return Ok(old_todo);
+        }
+        let todo = self
+            .record(&mut log, id, changes)
+            .await?
+            .expect("an updated todo is kept");
+        let kind = if todo.completed && !old_todo.completed {
+            TodoEventKind::Completed
+        } else {
+            TodoEventKind::Updated
+        };
+        self.publish(TodoEvent {
+            kind,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut log = self.log().await?;
+        let todo = self.read_projection().find(id)?;
+        self.record(&mut log, id, vec![Change::Deleted]).await?;
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Deleted,
+            todo,
+        });
+        Ok(())
+    }
+
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        let projection = self.read_projection();
+        let (total, open) = projection.todos().fold((0, 0), |(total, open), todo| {
+            (total + 1, open + i64::from(!todo.completed))
+        });
+        Ok(TodoStats { total, open })
+    }
+
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let projection = self.read_projection();
+        let updated = projection.todos().map(|todo| todo.updated_at).max();
+        Ok(updated.max(projection.last_deleted()))
+    }
+
+    async fn history(&self, id: i32) -> anyhow::Result<Vec<StoredEvent>> {
+        EventSourcedRepository::history(self, id).await
+    }
+}
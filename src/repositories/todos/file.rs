@@ -0,0 +1,72 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::events::{TodoEvent, TodoEventKind};
+use crate::repositories::file::{FileRepository, Record};
+use crate::repositories::todos::hash_map::updated;
+use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, TodoStats, UpdateTodo};
+
+#[async_trait]
+impl TodoRepository for FileRepository {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut log = self.log().await?;
+        let todo = Todo {
+            id: self.data.read_store_ref().todos.last_id() + 1,
+            text: payload.text,
+            completed: false,
+            updated_at: self.data.now(),
+        };
+        log.append(Record::Todo(todo.clone()), &self.data).await?;
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
+
+    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        TodoRepository::find(&self.data, id).await
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        TodoRepository::all(&self.data).await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let mut log = self.log().await?;
+        let old_todo = TodoRepository::find(&self.data, id).await?;
+        let todo = updated(&old_todo, payload, self.data.now());
+        log.append(Record::Todo(todo.clone()), &self.data).await?;
+        let kind = if todo.completed && !old_todo.completed {
+            TodoEventKind::Completed
+        } else {
+            TodoEventKind::Updated
+        };
+        self.publish(TodoEvent {
+            kind,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut log = self.log().await?;
+        let todo = TodoRepository::find(&self.data, id).await?;
+        let at = self.data.now();
+        log.append(Record::TodoDeleted { id, at }, &self.data)
+            .await?;
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Deleted,
+            todo,
+        });
+        Ok(())
+    }
+
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        self.data.stats().await
+    }
+
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.data.last_modified().await
+    }
+}
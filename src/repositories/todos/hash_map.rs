@@ -1,150 +1,260 @@
-#[cfg(test)]
-pub(crate) mod test_utils {
-    use anyhow::Context;
-    use axum::async_trait;
-
-    use crate::repositories::hash_map::test_utils::HashMapRepository;
-    use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, UpdateTodo};
-    use crate::repositories::RepositoryError;
-
-    #[async_trait]
-    impl TodoRepository for HashMapRepository {
-        async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
-            let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let todo = Todo::new(id, payload.text);
-            store.insert(id, todo.clone());
-            Ok(todo)
-        }
+use anyhow::Context;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
 
-        async fn find(&self, id: i32) -> anyhow::Result<Todo> {
-            let store = self.read_store_ref();
-            let todo = store
-                .get(&id)
-                .cloned()
-                .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
-            Ok(todo)
-        }
+use crate::events::{TodoEvent, TodoEventKind};
+use crate::repositories::hash_map::HashMapRepository;
+use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, TodoStats, UpdateTodo};
+use crate::repositories::RepositoryError;
 
-        async fn all(&self) -> anyhow::Result<Vec<Todo>> {
-            Ok(self.read_store_ref().values().cloned().collect())
-        }
+#[async_trait]
+impl TodoRepository for HashMapRepository {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let _writer = self.writer().await;
+        let mut store = self.write_store_ref();
+        let id = store.todos.next_id();
+        let todo = Todo {
+            id,
+            text: payload.text,
+            completed: false,
+            updated_at: self.now(),
+        };
+        store.todos.rows.insert(id, todo.clone());
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
 
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-            let mut store = self.write_store_ref();
-            let todo = store
-                .get(&id)
-                .with_context(|| RepositoryError::NotFound("id".to_string(), id))?;
-            let text = payload.text.unwrap_or(todo.text.clone());
-            let completed = payload.completed.unwrap_or(todo.completed);
-            let todo = Todo {
-                id,
-                text,
-                completed,
-            };
-            store.insert(id, todo.clone());
-            Ok(todo)
-        }
+    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        let store = self.read_store_ref();
+        let todo = store
+            .todos
+            .rows
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        Ok(todo)
+    }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
-            let mut store = self.write_store_ref();
-            store
-                .remove(&id)
-                .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
-            Ok(())
-        }
+    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        Ok(self
+            .read_store_ref()
+            .todos
+            .rows
+            .values()
+            .rev()
+            .cloned()
+            .collect())
     }
 
-    #[cfg(test)]
-    mod tests {
-        use crate::repositories::todos::UpdateTodo;
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let _writer = self.writer().await;
+        let mut store = self.write_store_ref();
+        let old_todo = store
+            .todos
+            .rows
+            .get(&id)
+            .with_context(|| RepositoryError::NotFound("id".to_string(), id))?;
+        let todo = updated(old_todo, payload, self.now());
+        let kind = if todo.completed && !old_todo.completed {
+            TodoEventKind::Completed
+        } else {
+            TodoEventKind::Updated
+        };
+        store.todos.rows.insert(id, todo.clone());
+        self.publish(TodoEvent {
+            kind,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
 
-        use super::*;
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let _writer = self.writer().await;
+        let mut store = self.write_store_ref();
+        let todo = store
+            .todos
+            .rows
+            .remove(&id)
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        store.last_deleted = Some(self.now());
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Deleted,
+            todo,
+        });
+        Ok(())
+    }
 
-        #[tokio::test]
-        async fn todo_create() {
-            let text = "todo text".to_string();
-            let id = 1;
-            let expected = Todo::new(id, text.clone());
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        let store = self.read_store_ref();
+        Ok(TodoStats {
+            total: store.todos.rows.len() as i64,
+            open: store
+                .todos
+                .rows
+                .values()
+                .filter(|todo| !todo.completed)
+                .count() as i64,
+        })
+    }
 
-            let repository = HashMapRepository::new();
-            let todo = repository.create(CreateTodo { text }).await.unwrap();
-            assert_eq!(expected, todo);
-        }
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let store = self.read_store_ref();
+        let updated = store.todos.rows.values().map(|todo| todo.updated_at).max();
+        Ok(updated.max(store.last_deleted))
+    }
+}
 
-        #[tokio::test]
-        async fn todo_find() {
-            let text = "todo text".to_string();
-            let id = 1;
-            let expected = Todo::new(id, text.clone());
+/// The todo `payload` makes of `old_todo` at `now`.
+pub(super) fn updated(old_todo: &Todo, payload: UpdateTodo, now: DateTime<Utc>) -> Todo {
+    Todo {
+        id: old_todo.id,
+        text: payload.text.unwrap_or(old_todo.text.clone()),
+        completed: payload.completed.unwrap_or(old_todo.completed),
+        updated_at: now,
+    }
+}
 
-            let repository = HashMapRepository::new();
-            repository
-                .create(CreateTodo { text })
-                .await
-                .expect("failed to create todo");
-            let todo = repository.find(id).await.unwrap();
-            assert_eq!(expected, todo);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        #[tokio::test]
-        async fn todo_all() {
-            let text = "todo text".to_string();
-            let id = 1;
-            let expected = Todo::new(id, text.clone());
-            let repository = HashMapRepository::new();
-            let _ = repository
-                .create(CreateTodo { text })
-                .await
-                .expect("failed to create todo");
-            let todo = repository.all().await.unwrap();
-            assert_eq!(vec![expected], todo);
-        }
+    #[tokio::test]
+    async fn todo_create() {
+        let text = "todo text".to_string();
+        let id = 1;
+        let expected = Todo::new(id, text.clone());
 
-        #[tokio::test]
-        async fn todo_update() {
-            let text = "todo text".to_string();
-            let id = 1;
-            let repository = HashMapRepository::new();
-            let _ = repository
-                .create(CreateTodo { text: text.clone() })
-                .await
-                .expect("failed to create todo");
+        let repository = HashMapRepository::new();
+        let todo = repository.create(CreateTodo { text }).await.unwrap();
+        assert_eq!(expected, todo);
+    }
 
-            let update_text = "update todo text".to_string();
-            let todo = repository
-                .update(
-                    id,
-                    UpdateTodo {
-                        text: Some(update_text.clone()),
-                        completed: Some(true),
-                    },
-                )
-                .await
-                .expect("failed update todo.");
-            assert_eq!(
-                Todo {
-                    id,
-                    text: update_text,
-                    completed: true,
+    #[tokio::test]
+    async fn todo_find() {
+        let text = "todo text".to_string();
+        let id = 1;
+        let expected = Todo::new(id, text.clone());
+
+        let repository = HashMapRepository::new();
+        repository
+            .create(CreateTodo { text })
+            .await
+            .expect("failed to create todo");
+        let todo = repository.find(id).await.unwrap();
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn todo_all() {
+        let text = "todo text".to_string();
+        let id = 1;
+        let expected = Todo::new(id, text.clone());
+        let repository = HashMapRepository::new();
+        let _ = repository
+            .create(CreateTodo { text })
+            .await
+            .expect("failed to create todo");
+        let todo = repository.all().await.unwrap();
+        assert_eq!(vec![expected], todo);
+    }
+
+    #[tokio::test]
+    async fn todo_update() {
+        let text = "todo text".to_string();
+        let id = 1;
+        let repository = HashMapRepository::new();
+        let _ = repository
+            .create(CreateTodo { text: text.clone() })
+            .await
+            .expect("failed to create todo");
+
+        let update_text = "update todo text".to_string();
+        let todo = repository
+            .update(
+                id,
+                UpdateTodo {
+                    text: Some(update_text.clone()),
+                    completed: Some(true),
                 },
-                todo
-            );
-        }
+            )
+            .await
+            .expect("failed update todo.");
+        assert_eq!(
+            Todo {
+                id,
+                text: update_text,
+                completed: true,
+                updated_at: repository.now(),
+            },
+            todo
+        );
+    }
+
+    #[tokio::test]
+    async fn todo_delete() {
+        let text = "todo text".to_string();
+        let id = 1;
+        let repository = HashMapRepository::new();
+        let _ = repository
+            .create(CreateTodo { text: text.clone() })
+            .await
+            .expect("failed to create todo");
+
+        let res = repository.delete(id).await;
+        assert!(res.is_ok());
+        assert!(repository.read_store_ref().todos.rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn todo_last_modified() {
+        let repository = HashMapRepository::new();
+        assert_eq!(None, repository.last_modified().await.unwrap());
 
-        #[tokio::test]
-        async fn todo_delete() {
-            let text = "todo text".to_string();
-            let id = 1;
-            let repository = HashMapRepository::new();
-            let _ = repository
-                .create(CreateTodo { text: text.clone() })
+        let created = repository.now();
+        repository
+            .create(CreateTodo {
+                text: "todo text".to_string(),
+            })
+            .await
+            .expect("failed to create todo");
+        assert_eq!(Some(created), repository.last_modified().await.unwrap());
+
+        let deleted = created + chrono::Duration::minutes(1);
+        repository.set_now(deleted);
+        repository.delete(1).await.expect("failed to delete todo");
+        assert_eq!(Some(deleted), repository.last_modified().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn todo_ids_are_not_reused() {
+        let repository = HashMapRepository::new();
+        for text in ["first", "second"] {
+            repository
+                .create(CreateTodo {
+                    text: text.to_string(),
+                })
                 .await
                 .expect("failed to create todo");
-
-            let res = repository.delete(id).await;
-            assert!(res.is_ok());
-            assert_eq!(repository.read_store_ref().len(), 0);
         }
+        repository.delete(2).await.expect("failed to delete todo");
+        let todo = repository
+            .create(CreateTodo {
+                text: "third".to_string(),
+            })
+            .await
+            .expect("failed to create todo");
+        assert_eq!(3, todo.id);
+
+        let ids: Vec<i32> = repository
+            .all()
+            .await
+            .unwrap()
+            .iter()
+            .map(|todo| todo.id)
+            .collect();
+        assert_eq!(vec![3, 1], ids);
     }
 }
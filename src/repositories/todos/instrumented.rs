@@ -0,0 +1,71 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::metrics::Metrics;
+use crate::repositories::todos::{
+    CreateTodo, StoredEvent, Todo, TodoRepository, TodoStats, UpdateTodo,
+};
+
+/// Decorates a `TodoRepository` with latency metrics for each of its methods.
+#[derive(Clone)]
+pub(crate) struct InstrumentedRepository<R> {
+    inner: R,
+    metrics: Metrics,
+}
+
+impl<R> InstrumentedRepository<R> {
+    pub(crate) fn new(inner: R, metrics: Metrics) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for InstrumentedRepository<R> {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.metrics
+            .observe_repository("create", self.inner.create(payload))
+            .await
+    }
+
+    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        self.metrics
+            .observe_repository("find", self.inner.find(id))
+            .await
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        self.metrics
+            .observe_repository("all", self.inner.all())
+            .await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        self.metrics
+            .observe_repository("update", self.inner.update(id, payload))
+            .await
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        self.metrics
+            .observe_repository("delete", self.inner.delete(id))
+            .await
+    }
+
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        self.metrics
+            .observe_repository("stats", self.inner.stats())
+            .await
+    }
+
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.metrics
+            .observe_repository("last_modified", self.inner.last_modified())
+            .await
+    }
+
+    async fn history(&self, id: i32) -> anyhow::Result<Vec<StoredEvent>> {
+        self.metrics
+            .observe_repository("history", self.inner.history(id))
+            .await
+    }
+}
@@ -1,12 +1,23 @@
 use axum::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::Connection;
 
+use crate::events::{TodoEvent, TodoEventKind};
+use crate::outbox;
 use crate::repositories::postgres::PostgresRepository;
-use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, UpdateTodo};
+use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, TodoStats, UpdateTodo};
 use crate::repositories::RepositoryError;
 
 #[async_trait]
 impl TodoRepository for PostgresRepository {
+    #[tracing::instrument(
+        name = "TodoRepository::create",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut conn = self.connection().await?;
+        let mut tx = conn.begin().await?;
         let todo = sqlx::query_as!(
             Todo,
             r#"
@@ -15,12 +26,24 @@ impl TodoRepository for PostgresRepository {
             "#,
             payload.text,
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
+        let event = TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: todo.clone(),
+        };
+        outbox::enqueue(&mut tx, &event).await?;
+        tx.commit().await?;
         Ok(todo)
     }
 
+    #[tracing::instrument(
+        name = "TodoRepository::find",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        let mut conn = self.read_connection().await?;
         let todo = sqlx::query_as!(
             Todo,
             r#"
@@ -29,7 +52,7 @@ impl TodoRepository for PostgresRepository {
             "#,
             id,
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *conn)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
@@ -38,7 +61,13 @@ impl TodoRepository for PostgresRepository {
         Ok(todo)
     }
 
+    #[tracing::instrument(
+        name = "TodoRepository::all",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        let mut conn = self.read_connection().await?;
         let todo = sqlx::query_as!(
             Todo,
             r#"
@@ -46,19 +75,40 @@ impl TodoRepository for PostgresRepository {
             ORDER BY id DESC
             "#,
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&mut *conn)
         .await?;
         Ok(todo)
     }
 
+    #[tracing::instrument(
+        name = "TodoRepository::update",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-        let old_todo = self.find(id).await?;
+        let mut conn = self.connection().await?;
+        let mut tx = conn.begin().await?;
+        let old_todo = sqlx::query_as!(
+            Todo,
+            r#"
+            SELECT * FROM todos
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
         let todo = sqlx::query_as!(
             Todo,
             r#"
             UPDATE todos
             SET
-            text = $1, completed = $2
+            text = $1, completed = $2, updated_at = now()
             WHERE id = $3
             RETURNING *
             "#,
@@ -66,25 +116,98 @@ impl TodoRepository for PostgresRepository {
             payload.completed.unwrap_or(old_todo.completed),
             id,
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
+        let kind = if todo.completed && !old_todo.completed {
+            TodoEventKind::Completed
+        } else {
+            TodoEventKind::Updated
+        };
+        let event = TodoEvent {
+            kind,
+            todo: todo.clone(),
+        };
+        outbox::enqueue(&mut tx, &event).await?;
+        tx.commit().await?;
         Ok(todo)
     }
 
+    #[tracing::instrument(
+        name = "TodoRepository::delete",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        sqlx::query!(
+        let mut conn = self.connection().await?;
+        let mut tx = conn.begin().await?;
+        let todo = sqlx::query_as!(
+            Todo,
             r#"
             DELETE FROM todos
             WHERE id = $1
+            RETURNING *
             "#,
             id,
         )
-        .execute(&self.pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
             _ => RepositoryError::Unexpected(e.to_string()),
         })?;
+        let event = TodoEvent {
+            kind: TodoEventKind::Deleted,
+            todo,
+        };
+        outbox::enqueue(&mut tx, &event).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO todo_list (last_deleted_at) VALUES (now())
+            ON CONFLICT (id) DO UPDATE SET last_deleted_at = excluded.last_deleted_at
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
         Ok(())
     }
+
+    #[tracing::instrument(
+        name = "TodoRepository::stats",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        let mut conn = self.read_connection().await?;
+        let stats = sqlx::query_as!(
+            TodoStats,
+            r#"
+            SELECT count(*) AS "total!", count(*) FILTER (WHERE NOT completed) AS "open!"
+            FROM todos
+            "#,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(stats)
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::last_modified",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let mut conn = self.read_connection().await?;
+        let last_modified = sqlx::query_scalar!(
+            r#"
+            SELECT GREATEST(
+                (SELECT max(updated_at) FROM todos),
+                (SELECT last_deleted_at FROM todo_list)
+            )
+            "#,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(last_modified)
+    }
 }
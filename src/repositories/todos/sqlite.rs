@@ -0,0 +1,208 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::Connection;
+
+use crate::events::{TodoEvent, TodoEventKind};
+use crate::repositories::sqlite::{lock_for_writing, SqliteRepository};
+use crate::repositories::todos::{CreateTodo, Todo, TodoRepository, TodoStats, UpdateTodo};
+use crate::repositories::RepositoryError;
+
+#[async_trait]
+impl TodoRepository for SqliteRepository {
+    #[tracing::instrument(
+        name = "TodoRepository::create",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut conn = self.connection().await?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            INSERT INTO todos (text, completed, updated_at) VALUES ($1, false, $2)
+            RETURNING *
+            "#,
+        )
+        .bind(payload.text)
+        .bind(self.now())
+        .fetch_one(&mut *conn)
+        .await?;
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::find",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
+        let mut conn = self.connection().await?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            SELECT * FROM todos
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+        Ok(todo)
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::all",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        let mut conn = self.connection().await?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            SELECT * FROM todos
+            ORDER BY id DESC
+            "#,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(todo)
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::update",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        // SQLite has no row locks, so the whole database stays locked from the read on
+        let mut conn = self.connection().await?;
+        let mut tx = conn.begin().await?;
+        lock_for_writing(&mut tx).await?;
+        let old_todo = sqlx::query_as::<_, Todo>(
+            r#"
+            SELECT * FROM todos
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id).into(),
+            // kept as it is, so that a busy database is told apart
+            _ => anyhow::Error::from(e),
+        })?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            UPDATE todos
+            SET
+            text = $1, completed = $2, updated_at = $3
+            WHERE id = $4
+            RETURNING *
+            "#,
+        )
+        .bind(payload.text.unwrap_or(old_todo.text))
+        .bind(payload.completed.unwrap_or(old_todo.completed))
+        .bind(self.now())
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        let kind = if todo.completed && !old_todo.completed {
+            TodoEventKind::Completed
+        } else {
+            TodoEventKind::Updated
+        };
+        self.publish(TodoEvent {
+            kind,
+            todo: todo.clone(),
+        });
+        Ok(todo)
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::delete",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        // the first statement writes, which takes the lock for the transaction
+        let mut conn = self.connection().await?;
+        let mut tx = conn.begin().await?;
+        let todo = sqlx::query_as::<_, Todo>(
+            r#"
+            DELETE FROM todos
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id).into(),
+            _ => anyhow::Error::from(e),
+        })?;
+        sqlx::query(
+            r#"
+            INSERT INTO todo_list (last_deleted_at) VALUES ($1)
+            ON CONFLICT (id) DO UPDATE SET last_deleted_at = excluded.last_deleted_at
+            "#,
+        )
+        .bind(self.now())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        self.publish(TodoEvent {
+            kind: TodoEventKind::Deleted,
+            todo,
+        });
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::stats",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn stats(&self) -> anyhow::Result<TodoStats> {
+        let mut conn = self.connection().await?;
+        let (total, open) = sqlx::query_as::<_, (i64, i64)>(
+            r#"
+            SELECT count(*), coalesce(sum(NOT completed), 0)
+            FROM todos
+            "#,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(TodoStats { total, open })
+    }
+
+    #[tracing::instrument(
+        name = "TodoRepository::last_modified",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn last_modified(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        // timestamps are all written in the same RFC 3339 form, which sorts like the instants
+        let mut conn = self.connection().await?;
+        let last_modified = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            r#"
+            SELECT max(modified_at) FROM (
+                SELECT max(updated_at) AS modified_at FROM todos
+                UNION ALL
+                SELECT last_deleted_at FROM todo_list
+            )
+            "#,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(last_modified)
+    }
+}
@@ -2,7 +2,10 @@ use axum::async_trait;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+mod file;
+mod hash_map;
 mod postgres;
+mod sqlite;
 
 #[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize)]
 pub(crate) struct User {
@@ -10,6 +13,8 @@ pub(crate) struct User {
     pub(crate) username: String,
     pub(crate) email: String,
     pub(crate) password_hash: String,
+    /// Disabled users cannot get new tokens, and the ones they hold are refused.
+    pub(crate) disabled: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize)]
@@ -19,11 +24,12 @@ pub(crate) struct CreateUser {
     pub(crate) password_hash: String,
 }
 
-#[derive(Clone, Debug, Deserialize, FromRow, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, FromRow, PartialEq, Serialize)]
 pub(crate) struct UpdateUser {
     pub(crate) username: Option<String>,
     pub(crate) email: Option<String>,
     pub(crate) password_hash: Option<String>,
+    pub(crate) disabled: Option<bool>,
 }
 
 #[async_trait]
@@ -31,6 +37,7 @@ pub(crate) trait UserRepository {
     async fn create(&self, payload: CreateUser) -> anyhow::Result<User>;
     async fn find_by_email(&self, email: &str) -> anyhow::Result<User>;
     async fn find_by_id(&self, id: i32) -> anyhow::Result<User>;
+    async fn all(&self) -> anyhow::Result<Vec<User>>;
     async fn update(&self, id: i32, payload: UpdateUser) -> anyhow::Result<User>;
     async fn delete(&self, id: i32) -> anyhow::Result<()>;
 }
@@ -0,0 +1,63 @@
+use axum::async_trait;
+
+use anyhow::Context;
+
+use crate::repositories::file::{FileRepository, Record};
+use crate::repositories::users::hash_map::{ensure_email_free, update};
+use crate::repositories::users::{CreateUser, UpdateUser, User, UserRepository};
+use crate::repositories::RepositoryError;
+
+#[async_trait]
+impl UserRepository for FileRepository {
+    async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+        let mut log = self.log().await?;
+        let user = {
+            let users = self.data.read_user_store_ref();
+            ensure_email_free(&users, None, &payload.email)?;
+            User {
+                id: users.last_id() + 1,
+                username: payload.username,
+                email: payload.email,
+                password_hash: payload.password_hash,
+                disabled: false,
+            }
+        };
+        log.append(Record::User(user.clone()), &self.data).await?;
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+        self.data.find_by_email(email).await
+    }
+
+    async fn find_by_id(&self, id: i32) -> anyhow::Result<User> {
+        self.data.find_by_id(id).await
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        UserRepository::all(&self.data).await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateUser) -> anyhow::Result<User> {
+        let mut log = self.log().await?;
+        let mut user = {
+            let users = self.data.read_user_store_ref();
+            if let Some(email) = &payload.email {
+                ensure_email_free(&users, Some(id), email)?;
+            }
+            users
+                .rows
+                .get(&id)
+                .cloned()
+                .with_context(|| RepositoryError::NotFound("id".to_string(), id))?
+        };
+        update(&mut user, payload);
+        log.append(Record::User(user.clone()), &self.data).await?;
+        Ok(user)
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut log = self.log().await?;
+        log.append(Record::UserDeleted { id }, &self.data).await
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::Context;
+use axum::async_trait;
+
+use crate::repositories::hash_map::{HashMapRepository, Table};
+use crate::repositories::users::{CreateUser, UpdateUser, User, UserRepository};
+use crate::repositories::RepositoryError;
+
+#[async_trait]
+impl UserRepository for HashMapRepository {
+    async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+        let _writer = self.writer().await;
+        let mut store = self.write_user_store_ref();
+        ensure_email_free(&store, None, &payload.email)?;
+        let id = store.next_id();
+        let user = User {
+            id,
+            username: payload.username,
+            email: payload.email,
+            password_hash: payload.password_hash,
+            disabled: false,
+        };
+        store.rows.insert(id, user.clone());
+        Ok(user)
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+        let store = self.read_user_store_ref();
+        let user = store
+            .rows
+            .values()
+            .find(|user| user.email == email)
+            .cloned()
+            .ok_or(RepositoryError::NotFound(
+                "email".to_string(),
+                email.to_string(),
+            ))?;
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, id: i32) -> anyhow::Result<User> {
+        let store = self.read_user_store_ref();
+        let user = store
+            .rows
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        Ok(user)
+    }
+
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        Ok(self.read_user_store_ref().rows.values().cloned().collect())
+    }
+
+    async fn update(&self, id: i32, payload: UpdateUser) -> anyhow::Result<User> {
+        let _writer = self.writer().await;
+        let mut store = self.write_user_store_ref();
+        if let Some(email) = &payload.email {
+            ensure_email_free(&store, Some(id), email)?;
+        }
+        let user = store
+            .rows
+            .get_mut(&id)
+            .with_context(|| RepositoryError::NotFound("id".to_string(), id))?;
+        update(user, payload);
+        Ok(user.clone())
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let _writer = self.writer().await;
+        self.remove_user(id);
+        Ok(())
+    }
+}
+
+/// Fails when a user other than `id` has `email` already.
+pub(super) fn ensure_email_free(
+    users: &Table<User>,
+    id: Option<i32>,
+    email: &str,
+) -> anyhow::Result<()> {
+    if users
+        .rows
+        .values()
+        .any(|user| Some(user.id) != id && user.email == email)
+    {
+        anyhow::bail!("a user with email [{}] already exists", email);
+    }
+    Ok(())
+}
+
+pub(super) fn update(user: &mut User, payload: UpdateUser) {
+    if let Some(username) = payload.username {
+        user.username = username;
+    }
+    if let Some(email) = payload.email {
+        user.email = email;
+    }
+    if let Some(password_hash) = payload.password_hash {
+        user.password_hash = password_hash;
+    }
+    if let Some(disabled) = payload.disabled {
+        user.disabled = disabled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::webhooks::{CreateWebhook, WebhookRepository};
+
+    fn create_user(email: &str) -> CreateUser {
+        CreateUser {
+            username: "user".to_string(),
+            email: email.to_string(),
+            password_hash: "hash".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn user_email_is_unique() {
+        let repository = HashMapRepository::new();
+        let first = UserRepository::create(&repository, create_user("a@example.com"))
+            .await
+            .unwrap();
+        assert!(
+            UserRepository::create(&repository, create_user("a@example.com"))
+                .await
+                .is_err()
+        );
+        let second = UserRepository::create(&repository, create_user("b@example.com"))
+            .await
+            .unwrap();
+        let taken = UpdateUser {
+            email: Some(first.email.clone()),
+            ..UpdateUser::default()
+        };
+        assert!(UserRepository::update(&repository, second.id, taken)
+            .await
+            .is_err());
+        assert_eq!(
+            first,
+            repository.find_by_email("a@example.com").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn user_delete_removes_webhooks() {
+        let repository = HashMapRepository::new();
+        let user = UserRepository::create(&repository, create_user("a@example.com"))
+            .await
+            .unwrap();
+        let payload = CreateWebhook::new(
+            "http://localhost/hook".to_string(),
+            "0123456789abcdef".to_string(),
+            vec![],
+        );
+        WebhookRepository::create(&repository, user.id, payload)
+            .await
+            .unwrap();
+
+        UserRepository::delete(&repository, user.id).await.unwrap();
+        assert!(repository.find_by_id(user.id).await.is_err());
+        assert!(WebhookRepository::all(&repository, user.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -6,7 +6,13 @@ use crate::repositories::RepositoryError;
 
 #[async_trait]
 impl UserRepository for PostgresRepository {
+    #[tracing::instrument(
+        name = "UserRepository::create",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+        let mut conn = self.connection().await?;
         let user = sqlx::query_as!(
             User,
             r#"
@@ -18,22 +24,28 @@ impl UserRepository for PostgresRepository {
             payload.email,
             payload.password_hash
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *conn)
         .await?;
         Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "UserRepository::find_by_email",
+        skip(self, email),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+        let mut conn = self.connection().await?;
         let user = sqlx::query_as!(
             User,
             r#"
-                SELECT id, username, email, password_hash
+                SELECT id, username, email, password_hash, disabled
                 FROM users
                 WHERE email = $1
                 "#,
             email
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *conn)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => {
@@ -44,17 +56,23 @@ impl UserRepository for PostgresRepository {
         Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "UserRepository::find_by_id",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn find_by_id(&self, id: i32) -> anyhow::Result<User> {
+        let mut conn = self.connection().await?;
         let user = sqlx::query_as!(
             User,
             r#"
-                SELECT id, username, email, password_hash
+                SELECT id, username, email, password_hash, disabled
                 FROM users
                 WHERE id = $1
                 "#,
             id
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *conn)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
@@ -63,28 +81,62 @@ impl UserRepository for PostgresRepository {
         Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "UserRepository::all",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        let mut conn = self.connection().await?;
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, username, email, password_hash, disabled
+            FROM users
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(users)
+    }
+
+    #[tracing::instrument(
+        name = "UserRepository::update",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn update(&self, id: i32, payload: UpdateUser) -> anyhow::Result<User> {
         let old_user = self.find_by_id(id).await?;
-        let todo = sqlx::query_as!(
+        let mut conn = self.connection().await?;
+        let user = sqlx::query_as!(
             User,
             r#"
             UPDATE users
             SET
-            username = $1, email = $2, password_hash = $3
-            WHERE id = $4
+            username = $1, email = $2, password_hash = $3, disabled = $4
+            WHERE id = $5
             RETURNING *
             "#,
             payload.username.unwrap_or(old_user.username),
             payload.email.unwrap_or(old_user.email),
             payload.password_hash.unwrap_or(old_user.password_hash),
+            payload.disabled.unwrap_or(old_user.disabled),
             id,
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *conn)
         .await?;
-        Ok(todo)
+        Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "UserRepository::delete",
+        skip(self),
+        fields(otel.kind = "client", db.system = "postgresql")
+    )]
     async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut conn = self.connection().await?;
+        // deleting is idempotent, so a missing user is no error
         sqlx::query!(
             r#"
             DELETE FROM users
@@ -92,12 +144,8 @@ impl UserRepository for PostgresRepository {
             "#,
             id,
         )
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+        .execute(&mut *conn)
+        .await?;
         Ok(())
     }
 }
@@ -0,0 +1,145 @@
+use axum::async_trait;
+
+use crate::repositories::sqlite::SqliteRepository;
+use crate::repositories::users::{CreateUser, UpdateUser, User, UserRepository};
+use crate::repositories::RepositoryError;
+
+#[async_trait]
+impl UserRepository for SqliteRepository {
+    #[tracing::instrument(
+        name = "UserRepository::create",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+        let mut conn = self.connection().await?;
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users(username, email, password_hash)
+            VALUES ($1, $2, $3)
+            RETURNING *
+            "#,
+        )
+        .bind(payload.username)
+        .bind(payload.email)
+        .bind(payload.password_hash)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "UserRepository::find_by_email",
+        skip(self, email),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+        let mut conn = self.connection().await?;
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, email, password_hash, disabled
+            FROM users
+            WHERE email = $1
+            "#,
+        )
+        .bind(email)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                RepositoryError::NotFound("email".to_string(), email.to_string())
+            }
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "UserRepository::find_by_id",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn find_by_id(&self, id: i32) -> anyhow::Result<User> {
+        let mut conn = self.connection().await?;
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, email, password_hash, disabled
+            FROM users
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "UserRepository::all",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        let mut conn = self.connection().await?;
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, email, password_hash, disabled
+            FROM users
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(users)
+    }
+
+    #[tracing::instrument(
+        name = "UserRepository::update",
+        skip(self, payload),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn update(&self, id: i32, payload: UpdateUser) -> anyhow::Result<User> {
+        let old_user = self.find_by_id(id).await?;
+        let mut conn = self.connection().await?;
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET
+            username = $1, email = $2, password_hash = $3, disabled = $4
+            WHERE id = $5
+            RETURNING *
+            "#,
+        )
+        .bind(payload.username.unwrap_or(old_user.username))
+        .bind(payload.email.unwrap_or(old_user.email))
+        .bind(payload.password_hash.unwrap_or(old_user.password_hash))
+        .bind(payload.disabled.unwrap_or(old_user.disabled))
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "UserRepository::delete",
+        skip(self),
+        fields(otel.kind = "client", db.system = "sqlite")
+    )]
+    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        let mut conn = self.connection().await?;
+        sqlx::query(
+            r#"
+            DELETE FROM users
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+use crate::events::TodoEventKind;
+
+mod file;
+mod hash_map;
+mod postgres;
+mod sqlite;
+
+#[async_trait]
+pub(crate) trait WebhookRepository: Clone + Send + Sync + 'static {
+    async fn create(&self, user_id: i32, payload: CreateWebhook) -> anyhow::Result<Webhook>;
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Webhook>;
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Webhook>>;
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook>;
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()>;
+    /// Webhooks of every user whose event filter accepts `kind`.
+    async fn subscribed_to(&self, kind: TodoEventKind) -> anyhow::Result<Vec<Webhook>>;
+    async fn record_delivery(
+        &self,
+        payload: CreateWebhookDelivery,
+    ) -> anyhow::Result<WebhookDelivery>;
+    async fn deliveries(
+        &self,
+        user_id: i32,
+        webhook_id: i32,
+    ) -> anyhow::Result<Vec<WebhookDelivery>>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub(crate) struct Webhook {
+    pub(crate) id: i32,
+    pub(crate) user_id: i32,
+    pub(crate) url: String,
+    #[serde(skip_serializing, default)]
+    pub(crate) secret: String,
+    /// Names of the accepted event kinds; empty means every kind.
+    pub(crate) events: Vec<String>,
+}
+
+impl Webhook {
+    pub(crate) fn accepts(&self, kind: TodoEventKind) -> bool {
+        self.events.is_empty() || self.events.iter().any(|event| event == kind.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub(crate) struct CreateWebhook {
+    #[validate(url(message = "url must be a valid URL"))]
+    #[validate(custom(function = "http_url", message = "url must be an http or https URL"))]
+    url: String,
+    #[validate(length(min = 16, message = "secret must be at least 16 characters"))]
+    secret: String,
+    #[serde(default)]
+    events: Vec<TodoEventKind>,
+}
+
+#[cfg(test)]
+impl CreateWebhook {
+    pub(crate) fn new(url: String, secret: String, events: Vec<TodoEventKind>) -> Self {
+        Self {
+            url,
+            secret,
+            events,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub(crate) struct UpdateWebhook {
+    #[validate(url(message = "url must be a valid URL"))]
+    #[validate(custom(function = "http_url", message = "url must be an http or https URL"))]
+    url: Option<String>,
+    #[validate(length(min = 16, message = "secret must be at least 16 characters"))]
+    secret: Option<String>,
+    events: Option<Vec<TodoEventKind>>,
+}
+
+fn http_url(url: &str) -> Result<(), ValidationError> {
+    match url::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(ValidationError::new("http_url")),
+    }
+}
+
+fn event_names(events: &[TodoEventKind]) -> Vec<String> {
+    events
+        .iter()
+        .map(|kind| kind.as_str().to_string())
+        .collect()
+}
+
+/// One attempt at delivering an event to a webhook.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub(crate) struct WebhookDelivery {
+    pub(crate) id: i32,
+    pub(crate) webhook_id: i32,
+    pub(crate) event: String,
+    pub(crate) payload: String,
+    pub(crate) attempt: i32,
+    pub(crate) status_code: Option<i32>,
+    pub(crate) error: Option<String>,
+    pub(crate) succeeded: bool,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CreateWebhookDelivery {
+    pub(crate) webhook_id: i32,
+    pub(crate) event: TodoEventKind,
+    pub(crate) payload: String,
+    pub(crate) attempt: i32,
+    pub(crate) status_code: Option<i32>,
+    pub(crate) error: Option<String>,
+    pub(crate) succeeded: bool,
+}
@@ -0,0 +1,73 @@
+use axum::async_trait;
+
+use crate::events::TodoEventKind;
+use crate::repositories::file::{FileRepository, Record};
+use crate::repositories::webhooks::hash_map::{delivery, update, webhook};
+use crate::repositories::webhooks::{
+    CreateWebhook, CreateWebhookDelivery, UpdateWebhook, Webhook, WebhookDelivery,
+    WebhookRepository,
+};
+
+#[async_trait]
+impl WebhookRepository for FileRepository {
+    async fn create(&self, user_id: i32, payload: CreateWebhook) -> anyhow::Result<Webhook> {
+        let mut log = self.log().await?;
+        let id = self.data.read_webhook_store_ref().webhooks.last_id() + 1;
+        let webhook = webhook(id, user_id, payload);
+        log.append(Record::Webhook(webhook.clone().into()), &self.data)
+            .await?;
+        Ok(webhook)
+    }
+
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Webhook> {
+        WebhookRepository::find(&self.data, user_id, id).await
+    }
+
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Webhook>> {
+        WebhookRepository::all(&self.data, user_id).await
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook> {
+        let mut log = self.log().await?;
+        let mut webhook = WebhookRepository::find(&self.data, user_id, id).await?;
+        update(&mut webhook, payload);
+        log.append(Record::Webhook(webhook.clone().into()), &self.data)
+            .await?;
+        Ok(webhook)
+    }
+
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()> {
+        let mut log = self.log().await?;
+        WebhookRepository::find(&self.data, user_id, id).await?;
+        log.append(Record::WebhookDeleted { id }, &self.data).await
+    }
+
+    async fn subscribed_to(&self, kind: TodoEventKind) -> anyhow::Result<Vec<Webhook>> {
+        self.data.subscribed_to(kind).await
+    }
+
+    async fn record_delivery(
+        &self,
+        payload: CreateWebhookDelivery,
+    ) -> anyhow::Result<WebhookDelivery> {
+        let mut log = self.log().await?;
+        let id = self.data.read_webhook_store_ref().deliveries.last_id() + 1;
+        let delivery = delivery(id, payload, self.data.now());
+        log.append(Record::Delivery(delivery.clone()), &self.data)
+            .await?;
+        Ok(delivery)
+    }
+
+    async fn deliveries(
+        &self,
+        user_id: i32,
+        webhook_id: i32,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        self.data.deliveries(user_id, webhook_id).await
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::Context;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::events::TodoEventKind;
+use crate::repositories::hash_map::HashMapRepository;
+use crate::repositories::webhooks::{
+    event_names, CreateWebhook, CreateWebhookDelivery, UpdateWebhook, Webhook, WebhookDelivery,
+    WebhookRepository,
+};
+use crate::repositories::RepositoryError;
+
+#[async_trait]
+impl WebhookRepository for HashMapRepository {
+    async fn create(&self, user_id: i32, payload: CreateWebhook) -> anyhow::Result<Webhook> {
+        let _writer = self.writer().await;
+        let mut store = self.write_webhook_store_ref();
+        let id = store.webhooks.next_id();
+        let webhook = webhook(id, user_id, payload);
+        store.webhooks.rows.insert(id, webhook.clone());
+        Ok(webhook)
+    }
+
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Webhook> {
+        let store = self.read_webhook_store_ref();
+        let webhook = store
+            .webhooks
+            .rows
+            .get(&id)
+            .filter(|webhook| webhook.user_id == user_id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        Ok(webhook)
+    }
+
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Webhook>> {
+        let store = self.read_webhook_store_ref();
+        Ok(store
+            .webhooks
+            .rows
+            .values()
+            .rev()
+            .filter(|webhook| webhook.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook> {
+        let _writer = self.writer().await;
+        let mut store = self.write_webhook_store_ref();
+        let webhook = store
+            .webhooks
+            .rows
+            .get_mut(&id)
+            .filter(|webhook| webhook.user_id == user_id)
+            .with_context(|| RepositoryError::NotFound("id".to_string(), id))?;
+        update(webhook, payload);
+        Ok(webhook.clone())
+    }
+
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()> {
+        let _writer = self.writer().await;
+        let mut store = self.write_webhook_store_ref();
+        store
+            .webhooks
+            .rows
+            .get(&id)
+            .filter(|webhook| webhook.user_id == user_id)
+            .ok_or(RepositoryError::NotFound("id".to_string(), id))?;
+        store.remove(id);
+        Ok(())
+    }
+
+    async fn subscribed_to(&self, kind: TodoEventKind) -> anyhow::Result<Vec<Webhook>> {
+        let store = self.read_webhook_store_ref();
+        Ok(store
+            .webhooks
+            .rows
+            .values()
+            .filter(|webhook| webhook.accepts(kind))
+            .cloned()
+            .collect())
+    }
+
+    async fn record_delivery(
+        &self,
+        payload: CreateWebhookDelivery,
+    ) -> anyhow::Result<WebhookDelivery> {
+        let _writer = self.writer().await;
+        let mut store = self.write_webhook_store_ref();
+        let delivery = delivery(store.deliveries.next_id(), payload, self.now());
+        store.deliveries.rows.insert(delivery.id, delivery.clone());
+        Ok(delivery)
+    }
+
+    async fn deliveries(
+        &self,
+        user_id: i32,
+        webhook_id: i32,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let webhook = self.find(user_id, webhook_id).await?;
+        let store = self.read_webhook_store_ref();
+        Ok(store
+            .deliveries
+            .rows
+            .values()
+            .rev()
+            .filter(|delivery| delivery.webhook_id == webhook.id)
+            .cloned()
+            .collect())
+    }
+}
+
+pub(super) fn webhook(id: i32, user_id: i32, payload: CreateWebhook) -> Webhook {
+    Webhook {
+        id,
+        user_id,
+        url: payload.url,
+        secret: payload.secret,
+        events: event_names(&payload.events),
+    }
+}
+
+pub(super) fn update(webhook: &mut Webhook, payload: UpdateWebhook) {
+    if let Some(url) = payload.url {
+        webhook.url = url;
+    }
+    if let Some(secret) = payload.secret {
+        webhook.secret = secret;
+    }
+    if let Some(events) = payload.events {
+        webhook.events = event_names(&events);
+    }
+}
+
+pub(super) fn delivery(
+    id: i32,
+    payload: CreateWebhookDelivery,
+    created_at: DateTime<Utc>,
+) -> WebhookDelivery {
+    WebhookDelivery {
+        id,
+        webhook_id: payload.webhook_id,
+        event: payload.event.as_str().to_string(),
+        payload: payload.payload,
+        attempt: payload.attempt,
+        status_code: payload.status_code,
+        error: payload.error,
+        succeeded: payload.succeeded,
+        created_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_webhook(events: Vec<TodoEventKind>) -> CreateWebhook {
+        CreateWebhook::new(
+            "http://localhost/hook".to_string(),
+            "0123456789abcdef".to_string(),
+            events,
+        )
+    }
+
+    #[tokio::test]
+    async fn webhook_find_is_scoped_to_user() {
+        let repository = HashMapRepository::new();
+        let webhook = repository
+            .create(1, create_webhook(vec![]))
+            .await
+            .expect("failed to create webhook");
+        assert_eq!(webhook, repository.find(1, webhook.id).await.unwrap());
+        assert!(repository.find(2, webhook.id).await.is_err());
+        assert!(repository.delete(2, webhook.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook_subscribed_to() {
+        let repository = HashMapRepository::new();
+        let all = repository
+            .create(1, create_webhook(vec![]))
+            .await
+            .expect("failed to create webhook");
+        let completed = repository
+            .create(2, create_webhook(vec![TodoEventKind::Completed]))
+            .await
+            .expect("failed to create webhook");
+
+        let webhooks = repository
+            .subscribed_to(TodoEventKind::Completed)
+            .await
+            .unwrap();
+        assert_eq!(vec![all.clone(), completed], webhooks);
+        let webhooks = repository
+            .subscribed_to(TodoEventKind::Created)
+            .await
+            .unwrap();
+        assert_eq!(vec![all], webhooks);
+    }
+}
@@ -0,0 +1,178 @@
+use axum::async_trait;
+
+use crate::events::TodoEventKind;
+use crate::repositories::postgres::PostgresRepository;
+use crate::repositories::webhooks::{
+    event_names, CreateWebhook, CreateWebhookDelivery, UpdateWebhook, Webhook, WebhookDelivery,
+    WebhookRepository,
+};
+use crate::repositories::RepositoryError;
+
+#[async_trait]
+impl WebhookRepository for PostgresRepository {
+    async fn create(&self, user_id: i32, payload: CreateWebhook) -> anyhow::Result<Webhook> {
+        let mut conn = self.connection().await?;
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            INSERT INTO webhooks (user_id, url, secret, events) VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+            user_id,
+            payload.url,
+            payload.secret,
+            &event_names(&payload.events),
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(webhook)
+    }
+
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Webhook> {
+        let mut conn = self.connection().await?;
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT * FROM webhooks
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id,
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+        Ok(webhook)
+    }
+
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Webhook>> {
+        let mut conn = self.connection().await?;
+        let webhooks = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT * FROM webhooks
+            WHERE user_id = $1
+            ORDER BY id DESC
+            "#,
+            user_id,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook> {
+        let old_webhook = self.find(user_id, id).await?;
+        let mut conn = self.connection().await?;
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            UPDATE webhooks
+            SET
+            url = $1, secret = $2, events = $3
+            WHERE id = $4
+            RETURNING *
+            "#,
+            payload.url.unwrap_or(old_webhook.url),
+            payload.secret.unwrap_or(old_webhook.secret),
+            &payload
+                .events
+                .map(|events| event_names(&events))
+                .unwrap_or(old_webhook.events),
+            id,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(webhook)
+    }
+
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()> {
+        let mut conn = self.connection().await?;
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM webhooks
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id,
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| RepositoryError::<i32>::Unexpected(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound("id".to_string(), id).into());
+        }
+        Ok(())
+    }
+
+    async fn subscribed_to(&self, kind: TodoEventKind) -> anyhow::Result<Vec<Webhook>> {
+        let mut conn = self.connection().await?;
+        let webhooks = sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT * FROM webhooks
+            WHERE cardinality(events) = 0 OR $1 = ANY(events)
+            ORDER BY id
+            "#,
+            kind.as_str(),
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(webhooks)
+    }
+
+    async fn record_delivery(
+        &self,
+        payload: CreateWebhookDelivery,
+    ) -> anyhow::Result<WebhookDelivery> {
+        let mut conn = self.connection().await?;
+        let delivery = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            INSERT INTO webhook_deliveries
+            (webhook_id, event, payload, attempt, status_code, error, succeeded)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+            payload.webhook_id,
+            payload.event.as_str(),
+            payload.payload,
+            payload.attempt,
+            payload.status_code,
+            payload.error,
+            payload.succeeded,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(delivery)
+    }
+
+    async fn deliveries(
+        &self,
+        user_id: i32,
+        webhook_id: i32,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let webhook = self.find(user_id, webhook_id).await?;
+        let mut conn = self.connection().await?;
+        let deliveries = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+            SELECT * FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ORDER BY id DESC
+            "#,
+            webhook.id,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(deliveries)
+    }
+}
@@ -0,0 +1,199 @@
+use axum::async_trait;
+use sqlx::types::Json;
+use sqlx::FromRow;
+
+use crate::events::TodoEventKind;
+use crate::repositories::sqlite::SqliteRepository;
+use crate::repositories::webhooks::{
+    event_names, CreateWebhook, CreateWebhookDelivery, UpdateWebhook, Webhook, WebhookDelivery,
+    WebhookRepository,
+};
+use crate::repositories::RepositoryError;
+
+/// A webhook as stored, with its event kinds in a JSON array since SQLite has no arrays.
+#[derive(FromRow)]
+struct WebhookRow {
+    id: i32,
+    user_id: i32,
+    url: String,
+    secret: String,
+    events: Json<Vec<String>>,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Self {
+            id: row.id,
+            user_id: row.user_id,
+            url: row.url,
+            secret: row.secret,
+            events: row.events.0,
+        }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for SqliteRepository {
+    async fn create(&self, user_id: i32, payload: CreateWebhook) -> anyhow::Result<Webhook> {
+        let mut conn = self.connection().await?;
+        let webhook = sqlx::query_as::<_, WebhookRow>(
+            r#"
+            INSERT INTO webhooks (user_id, url, secret, events) VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(payload.url)
+        .bind(payload.secret)
+        .bind(Json(event_names(&payload.events)))
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(webhook.into())
+    }
+
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Webhook> {
+        let mut conn = self.connection().await?;
+        let webhook = sqlx::query_as::<_, WebhookRow>(
+            r#"
+            SELECT * FROM webhooks
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound("id".to_string(), id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+        Ok(webhook.into())
+    }
+
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Webhook>> {
+        let mut conn = self.connection().await?;
+        let webhooks = sqlx::query_as::<_, WebhookRow>(
+            r#"
+            SELECT * FROM webhooks
+            WHERE user_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(webhooks.into_iter().map(Webhook::from).collect())
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook> {
+        let old_webhook = self.find(user_id, id).await?;
+        let mut conn = self.connection().await?;
+        let webhook = sqlx::query_as::<_, WebhookRow>(
+            r#"
+            UPDATE webhooks
+            SET
+            url = $1, secret = $2, events = $3
+            WHERE id = $4
+            RETURNING *
+            "#,
+        )
+        .bind(payload.url.unwrap_or(old_webhook.url))
+        .bind(payload.secret.unwrap_or(old_webhook.secret))
+        .bind(Json(
+            payload
+                .events
+                .map(|events| event_names(&events))
+                .unwrap_or(old_webhook.events),
+        ))
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(webhook.into())
+    }
+
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()> {
+        let mut conn = self.connection().await?;
+        let result = sqlx::query(
+            r#"
+            DELETE FROM webhooks
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| RepositoryError::<i32>::Unexpected(e.to_string()))?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound("id".to_string(), id).into());
+        }
+        Ok(())
+    }
+
+    async fn subscribed_to(&self, kind: TodoEventKind) -> anyhow::Result<Vec<Webhook>> {
+        let mut conn = self.connection().await?;
+        let webhooks = sqlx::query_as::<_, WebhookRow>(
+            r#"
+            SELECT * FROM webhooks
+            WHERE json_array_length(events) = 0
+            OR EXISTS (SELECT 1 FROM json_each(events) WHERE value = $1)
+            ORDER BY id
+            "#,
+        )
+        .bind(kind.as_str())
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(webhooks.into_iter().map(Webhook::from).collect())
+    }
+
+    async fn record_delivery(
+        &self,
+        payload: CreateWebhookDelivery,
+    ) -> anyhow::Result<WebhookDelivery> {
+        let mut conn = self.connection().await?;
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(
+            r#"
+            INSERT INTO webhook_deliveries
+            (webhook_id, event, payload, attempt, status_code, error, succeeded, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+        )
+        .bind(payload.webhook_id)
+        .bind(payload.event.as_str())
+        .bind(payload.payload)
+        .bind(payload.attempt)
+        .bind(payload.status_code)
+        .bind(payload.error)
+        .bind(payload.succeeded)
+        .bind(self.now())
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(delivery)
+    }
+
+    async fn deliveries(
+        &self,
+        user_id: i32,
+        webhook_id: i32,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let webhook = self.find(user_id, webhook_id).await?;
+        let mut conn = self.connection().await?;
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+            r#"
+            SELECT * FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(webhook.id)
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(deliveries)
+    }
+}
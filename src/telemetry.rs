@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use axum::extract::MatchedPath;
+use axum::http::{Request, Response};
+use tracing::field::Empty;
+use tracing::Span;
+
+#[cfg(feature = "otel")]
+pub(crate) mod otel;
+
+/// Header carrying the id that correlates the logs of one request.
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Opens the span every log line of a request is recorded in.
+///
+/// Runs after the request id was assigned, so the span always carries one.
+pub(crate) fn make_span<B>(req: &Request<B>) -> Span {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| req.uri().path());
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        route,
+        request_id,
+        status = Empty,
+        latency_ms = Empty,
+        otel.name = Empty,
+        otel.kind = Empty,
+    );
+    #[cfg(feature = "otel")]
+    {
+        span.record("otel.name", format!("{} {}", req.method(), route));
+        span.record("otel.kind", "server");
+        otel::set_parent(&span, req.headers());
+    }
+    span
+}
+
+pub(crate) fn on_response<B>(res: &Response<B>, latency: Duration, span: &Span) {
+    span.record("status", res.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    tracing::info!("finished processing request");
+}
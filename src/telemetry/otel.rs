@@ -0,0 +1,117 @@
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::TelemetryConfig;
+
+/// Batches finished spans to the OTLP/HTTP collector at `config.otlp_endpoint`.
+pub(crate) fn tracer_provider(config: &TelemetryConfig) -> Result<TracerProvider, TraceError> {
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(&config.otlp_endpoint),
+    )
+    .build_span_exporter()?;
+    let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+        .with_config(trace::config().with_resource(resource))
+        .build())
+}
+
+/// Continues the trace of the caller when the request carries a W3C `traceparent`.
+pub(crate) fn set_parent(span: &Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(context);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use axum::body::{Body, Bytes};
+    use axum::http::{Request, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use opentelemetry::trace::TracerProvider as _;
+    use tokio::sync::mpsc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::telemetry;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    /// Stands in for an OTLP collector and hands over the body of every export request.
+    async fn spawn_collector() -> (SocketAddr, mpsc::UnboundedReceiver<Bytes>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/v1/traces",
+            post(move |body: Bytes| async move {
+                sender.send(body).unwrap();
+                StatusCode::OK
+            }),
+        );
+        let server = axum::Server::bind(&([127, 0, 0, 1], 0).into()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, receiver)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn export_request_span_with_remote_parent() {
+        let (addr, mut exports) = spawn_collector().await;
+        let provider = tracer_provider(&TelemetryConfig {
+            otlp_endpoint: format!("http://{}/v1/traces", addr),
+            service_name: "my-todo-test".to_string(),
+        })
+        .unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let req = Request::builder()
+                .uri("/todos/1")
+                .header(
+                    "traceparent",
+                    format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+                )
+                .body(Body::empty())
+                .unwrap();
+            let span = telemetry::make_span(&req);
+            let _entered = span.enter();
+        });
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let export = tokio::time::timeout(Duration::from_secs(5), exports.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        // the protobuf payload carries ids as raw bytes and names as plain strings
+        let trace_id = hex::decode(TRACE_ID).unwrap();
+        assert!(export.windows(trace_id.len()).any(|w| w == trace_id));
+        assert!(export.windows(12).any(|w| w == b"GET /todos/1"));
+        assert!(export.windows(12).any(|w| w == b"my-todo-test"));
+    }
+}
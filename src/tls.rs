@@ -0,0 +1,248 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use axum::extract::Host;
+use axum::http::uri::{Authority, Scheme, Uri};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::TlsConfig;
+
+/// How often the certificate files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The certificate served over HTTPS, swapped for the one on disk whenever the files change
+/// or the process receives SIGHUP.
+pub(crate) struct CertificateReloader {
+    config: RustlsConfig,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    modified: (SystemTime, SystemTime),
+    hangup: Hangup,
+}
+
+impl CertificateReloader {
+    pub(crate) async fn load(tls: &TlsConfig) -> anyhow::Result<Self> {
+        let cert_path = PathBuf::from(&tls.cert_path);
+        let key_path = PathBuf::from(&tls.key_path);
+        let modified = modified(&cert_path, &key_path)?;
+        let config = RustlsConfig::from_pem_file(&cert_path, &key_path)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to load certificate [{}] with key [{}]",
+                    cert_path.display(),
+                    key_path.display()
+                )
+            })?;
+        Ok(Self {
+            config,
+            cert_path,
+            key_path,
+            modified,
+            hangup: Hangup::install()?,
+        })
+    }
+
+    /// The configuration to serve with; it picks up every reload.
+    pub(crate) fn config(&self) -> RustlsConfig {
+        self.config.clone()
+    }
+
+    async fn reload(&mut self) -> anyhow::Result<()> {
+        let modified = modified(&self.cert_path, &self.key_path)?;
+        self.config
+            .reload_from_pem_file(&self.cert_path, &self.key_path)
+            .await?;
+        self.modified = modified;
+        tracing::info!("reloaded certificate [{}]", self.cert_path.display());
+        Ok(())
+    }
+
+    async fn reload_if_changed(&mut self) -> anyhow::Result<()> {
+        if modified(&self.cert_path, &self.key_path)? != self.modified {
+            self.reload().await?;
+        }
+        Ok(())
+    }
+
+    /// Reloads until `shutdown` is cancelled; a certificate that fails to load is reported
+    /// and the previous one stays in use.
+    pub(crate) async fn watch(mut self, shutdown: CancellationToken) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            let forced = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => false,
+                _ = self.hangup.recv() => true,
+            };
+            let result = if forced {
+                tracing::info!("received SIGHUP");
+                self.reload().await
+            } else {
+                self.reload_if_changed().await
+            };
+            if let Err(e) = result {
+                tracing::error!("failed to reload certificate: {:#}", e);
+            }
+        }
+    }
+}
+
+/// SIGHUP, which never arrives on platforms without it.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Signal,
+}
+
+impl Hangup {
+    #[cfg(unix)]
+    fn install() -> io::Result<Self> {
+        Ok(Self {
+            signal: signal(SignalKind::hangup())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn install() -> io::Result<Self> {
+        Ok(Self {})
+    }
+
+    #[cfg(unix)]
+    async fn recv(&mut self) {
+        self.signal.recv().await;
+    }
+
+    #[cfg(not(unix))]
+    async fn recv(&mut self) {
+        std::future::pending::<()>().await
+    }
+}
+
+fn modified(cert_path: &Path, key_path: &Path) -> io::Result<(SystemTime, SystemTime)> {
+    Ok((
+        fs::metadata(cert_path)?.modified()?,
+        fs::metadata(key_path)?.modified()?,
+    ))
+}
+
+/// Answers every plain HTTP request with a permanent redirect to the same url over HTTPS.
+pub(crate) fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |Host(host): Host, uri: Uri| async move {
+        redirect_to_https(&host, uri, https_port)
+    })
+}
+
+fn redirect_to_https(host: &str, uri: Uri, https_port: u16) -> Response {
+    let Ok(authority) = host.parse::<Authority>() else {
+        return (StatusCode::BAD_REQUEST, "Invalid host").into_response();
+    };
+    let authority = if https_port == 443 {
+        authority.host().to_string()
+    } else {
+        format!("{}:{}", authority.host(), https_port)
+    };
+    let mut parts = uri.into_parts();
+    parts.scheme = Some(Scheme::HTTPS);
+    parts.authority = authority.parse().ok();
+    if parts.path_and_query.is_none() {
+        parts.path_and_query = Some("/".parse().unwrap());
+    }
+    match Uri::from_parts(parts) {
+        Ok(location) => Redirect::permanent(&location.to_string()).into_response(),
+        Err(_) => (StatusCode::BAD_REQUEST, "Invalid host").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{header, Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn write_certificate(tls: &TlsConfig) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(&tls.cert_path, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(&tls.key_path, cert.serialize_private_key_pem()).unwrap();
+    }
+
+    fn tls_config(dir: &tempfile::TempDir) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.path().join("cert.pem").display().to_string(),
+            key_path: dir.path().join("key.pem").display().to_string(),
+            redirect_port: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_changed_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = tls_config(&dir);
+        write_certificate(&tls);
+        let mut reloader = CertificateReloader::load(&tls).await.unwrap();
+        let served = reloader.config();
+        let first = served.get_inner();
+
+        reloader.reload_if_changed().await.unwrap();
+        assert!(Arc::ptr_eq(&first, &served.get_inner()));
+
+        write_certificate(&tls);
+        // file systems with coarse timestamps would not notice a rewrite within the same tick
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::File::options()
+            .write(true)
+            .open(&tls.cert_path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        reloader.reload_if_changed().await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &served.get_inner()));
+    }
+
+    #[tokio::test]
+    async fn keep_certificate_when_reload_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = tls_config(&dir);
+        write_certificate(&tls);
+        let mut reloader = CertificateReloader::load(&tls).await.unwrap();
+        let first = reloader.config().get_inner();
+
+        fs::write(&tls.key_path, "not a key").unwrap();
+        assert!(reloader.reload().await.is_err());
+        assert!(Arc::ptr_eq(&first, &reloader.config().get_inner()));
+    }
+
+    #[tokio::test]
+    async fn redirect_plain_http_to_https() {
+        for (host, port, location) in [
+            ("example.com", 443, "https://example.com/todos?id=1"),
+            (
+                "example.com:8080",
+                8443,
+                "https://example.com:8443/todos?id=1",
+            ),
+            ("[::1]:80", 3443, "https://[::1]:3443/todos?id=1"),
+        ] {
+            let req = Request::builder()
+                .uri("/todos?id=1")
+                .header(header::HOST, host)
+                .body(Body::empty())
+                .unwrap();
+            let res = redirect_app(port).oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::PERMANENT_REDIRECT, res.status());
+            assert_eq!(location, res.headers()[header::LOCATION]);
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use sha2::Sha256;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::config::WebhooksConfig;
+use crate::events::{EventSink, TodoEvent, TodoEventKind};
+use crate::repositories::webhooks::{CreateWebhookDelivery, Webhook, WebhookRepository};
+use crate::webhooks::targets::{Resolver, Targets};
+
+pub(crate) mod queue;
+pub(crate) mod targets;
+
+pub(crate) const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub(crate) const EVENT_HEADER: &str = "X-Webhook-Event";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following `attempt`, doubling each time up to `max_backoff`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC of `body` keyed with the webhook secret, sent in `SIGNATURE_HEADER`.
+pub(crate) fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs todo events to the webhooks subscribed to them, recording every attempt.
+///
+/// Deliveries run on `tasks` so that shutdown can wait for them; once `shutdown` is cancelled
+/// the attempt in flight completes but no retry is scheduled.
+///
+/// Deliveries only reach the addresses `Targets` allows, and redirects are not followed, as
+/// they could lead anywhere.
+#[derive(Debug)]
+pub(crate) struct WebhookDispatcher<W> {
+    repository: Arc<W>,
+    client: reqwest::Client,
+    targets: Targets,
+    policy: RetryPolicy,
+    tasks: TaskTracker,
+    shutdown: CancellationToken,
+}
+
+impl<W> Clone for WebhookDispatcher<W> {
+    fn clone(&self) -> Self {
+        Self {
+            repository: Arc::clone(&self.repository),
+            client: self.client.clone(),
+            targets: self.targets.clone(),
+            policy: self.policy.clone(),
+            tasks: self.tasks.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+impl<W: WebhookRepository> WebhookDispatcher<W> {
+    pub(crate) fn new(
+        repository: Arc<W>,
+        policy: RetryPolicy,
+        config: &WebhooksConfig,
+        tasks: TaskTracker,
+        shutdown: CancellationToken,
+    ) -> anyhow::Result<Self> {
+        let targets = Targets::new(config);
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            // a proxy would resolve the host instead of `Resolver`
+            .no_proxy()
+            .dns_resolver(Arc::new(Resolver {
+                targets: targets.clone(),
+            }))
+            .build()?;
+        Ok(Self {
+            repository,
+            client,
+            targets,
+            policy,
+            tasks,
+            shutdown,
+        })
+    }
+
+    async fn deliver(self, webhook: Webhook, event: TodoEvent) {
+        let payload = match serde_json::to_string(&event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!("failed to serialize todo event: {}", e);
+                return;
+            }
+        };
+        for attempt in 1..=self.policy.max_attempts {
+            if self.attempt(&webhook, event.kind, &payload, attempt).await {
+                return;
+            }
+            if attempt < self.policy.max_attempts {
+                tokio::select! {
+                    _ = self.shutdown.cancelled() => {
+                        tracing::warn!(
+                            "stopped retrying {:?} to webhook {} on shutdown",
+                            event.kind,
+                            webhook.id
+                        );
+                        return;
+                    }
+                    _ = tokio::time::sleep(self.policy.backoff(attempt)) => {}
+                }
+            }
+        }
+        tracing::warn!(
+            "gave up delivering {:?} to webhook {} after {} attempts",
+            event.kind,
+            webhook.id,
+            self.policy.max_attempts
+        );
+    }
+
+    /// POSTs `payload` to `webhook` once and records the attempt, telling whether it succeeded.
+    async fn attempt(
+        &self,
+        webhook: &Webhook,
+        kind: TodoEventKind,
+        payload: &str,
+        attempt: u32,
+    ) -> bool {
+        let (status_code, error) = match self.targets.check(&webhook.url) {
+            Ok(()) => self.post(webhook, kind, payload).await,
+            Err(e) => (None, Some(e)),
+        };
+        let succeeded = error.is_none();
+        let delivery = CreateWebhookDelivery {
+            webhook_id: webhook.id,
+            event: kind,
+            payload: payload.to_string(),
+            attempt: attempt as i32,
+            status_code: status_code.map(i32::from),
+            error,
+            succeeded,
+        };
+        if let Err(e) = self.repository.record_delivery(delivery).await {
+            tracing::error!("failed to record delivery to webhook {}: {}", webhook.id, e);
+        }
+        succeeded
+    }
+
+    /// The status code the webhook answered with, and what went wrong if anything.
+    async fn post(
+        &self,
+        webhook: &Webhook,
+        kind: TodoEventKind,
+        payload: &str,
+    ) -> (Option<u16>, Option<String>) {
+        let result = self
+            .client
+            .post(&webhook.url)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, payload.as_bytes()))
+            .header(EVENT_HEADER, kind.as_str())
+            .body(payload.to_string())
+            .send()
+            .await;
+        match result {
+            Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+            Ok(res) => (
+                Some(res.status().as_u16()),
+                Some(format!("unexpected status {}", res.status())),
+            ),
+            Err(e) => (
+                e.status().map(|status| status.as_u16()),
+                Some(e.to_string()),
+            ),
+        }
+    }
+}
+
+/// Looks up the webhooks subscribed to the event and spawns their deliveries.
+///
+/// Retries happen in the spawned tasks, so deliveries still pending are lost if the process
+/// stops; the Postgres backend keeps them in a `queue::WebhookQueue` instead.
+#[async_trait]
+impl<W: WebhookRepository> EventSink for WebhookDispatcher<W> {
+    async fn deliver(&self, event: &TodoEvent) -> anyhow::Result<()> {
+        let webhooks = self.repository.subscribed_to(event.kind).await?;
+        for webhook in webhooks {
+            self.tasks
+                .spawn(self.clone().deliver(webhook, event.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::Redirect;
+    use axum::routing::post;
+    use axum::Router;
+    use tokio::sync::mpsc;
+
+    use crate::repositories::hash_map::HashMapRepository;
+    use crate::repositories::todos::Todo;
+    use crate::repositories::webhooks::CreateWebhook;
+
+    use super::*;
+
+    pub(super) const SECRET: &str = "0123456789abcdef";
+
+    #[derive(Clone)]
+    struct Receiver {
+        failures: Arc<AtomicUsize>,
+        received: mpsc::UnboundedSender<(HeaderMap, String)>,
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let _ = receiver.received.send((headers, body));
+        let remaining = receiver.failures.load(Ordering::SeqCst);
+        if remaining > 0 {
+            receiver.failures.store(remaining - 1, Ordering::SeqCst);
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::NO_CONTENT
+        }
+    }
+
+    /// Starts a local stand-in receiver which fails the first `failures` requests.
+    pub(super) fn spawn_receiver(
+        failures: usize,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<(HeaderMap, String)>) {
+        let (sender, received) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .route("/redirect", post(|| async { Redirect::temporary("/hook") }))
+            .with_state(Receiver {
+                failures: Arc::new(AtomicUsize::new(failures)),
+                received: sender,
+            });
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, received)
+    }
+
+    pub(super) fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+        }
+    }
+
+    /// Lets deliveries reach the receivers of `spawn_receiver`.
+    pub(super) fn loopback() -> WebhooksConfig {
+        WebhooksConfig {
+            allowed_networks: vec!["127.0.0.0/8".to_string()],
+            ..WebhooksConfig::default()
+        }
+    }
+
+    fn dispatcher(repository: &Arc<HashMapRepository>) -> WebhookDispatcher<HashMapRepository> {
+        WebhookDispatcher::new(
+            Arc::clone(repository),
+            policy(),
+            &loopback(),
+            TaskTracker::new(),
+            CancellationToken::new(),
+        )
+        .unwrap()
+    }
+
+    pub(super) fn created_event() -> TodoEvent {
+        TodoEvent {
+            kind: TodoEventKind::Created,
+            todo: Todo::new(1, "todo".to_string()),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+        assert_eq!(Duration::from_secs(1), policy.backoff(1));
+        assert_eq!(Duration::from_secs(2), policy.backoff(2));
+        assert_eq!(Duration::from_secs(4), policy.backoff(3));
+        assert_eq!(Duration::from_secs(5), policy.backoff(4));
+    }
+
+    #[tokio::test]
+    async fn deliver_signed_payload() {
+        let (addr, mut received) = spawn_receiver(0);
+        let repository = Arc::new(HashMapRepository::new());
+        let webhook = repository
+            .create(
+                1,
+                CreateWebhook::new(format!("http://{}/hook", addr), SECRET.to_string(), vec![]),
+            )
+            .await
+            .expect("failed to create webhook");
+        let dispatcher = dispatcher(&repository);
+        let event = created_event();
+        dispatcher
+            .clone()
+            .deliver(webhook.clone(), event.clone())
+            .await;
+
+        let (headers, body) = received.recv().await.unwrap();
+        assert_eq!(serde_json::to_string(&event).unwrap(), body);
+        assert_eq!(sign(SECRET, body.as_bytes()), headers[SIGNATURE_HEADER]);
+        assert_eq!("created", headers[EVENT_HEADER]);
+
+        let deliveries = repository.deliveries(1, webhook.id).await.unwrap();
+        assert_eq!(1, deliveries.len());
+        assert!(deliveries[0].succeeded);
+        assert_eq!(Some(204), deliveries[0].status_code);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_delivery() {
+        let (addr, mut received) = spawn_receiver(2);
+        let repository = Arc::new(HashMapRepository::new());
+        let webhook = repository
+            .create(
+                1,
+                CreateWebhook::new(format!("http://{}/hook", addr), SECRET.to_string(), vec![]),
+            )
+            .await
+            .expect("failed to create webhook");
+        let dispatcher = dispatcher(&repository);
+        dispatcher.deliver(webhook.clone(), created_event()).await;
+
+        for _ in 0..3 {
+            received.recv().await.unwrap();
+        }
+        let deliveries = repository.deliveries(1, webhook.id).await.unwrap();
+        let attempts: Vec<(i32, bool)> = deliveries
+            .iter()
+            .map(|delivery| (delivery.attempt, delivery.succeeded))
+            .collect();
+        assert_eq!(vec![(3, true), (2, false), (1, false)], attempts);
+        assert_eq!(Some(500), deliveries[2].status_code);
+    }
+
+    #[tokio::test]
+    async fn refuse_denied_address() {
+        let (addr, mut received) = spawn_receiver(0);
+        let repository = Arc::new(HashMapRepository::new());
+        let webhook = repository
+            .create(
+                1,
+                CreateWebhook::new(format!("http://{}/hook", addr), SECRET.to_string(), vec![]),
+            )
+            .await
+            .expect("failed to create webhook");
+        let dispatcher = WebhookDispatcher::new(
+            Arc::clone(&repository),
+            RetryPolicy {
+                max_attempts: 1,
+                ..policy()
+            },
+            &WebhooksConfig::default(),
+            TaskTracker::new(),
+            CancellationToken::new(),
+        )
+        .unwrap();
+        dispatcher.deliver(webhook.clone(), created_event()).await;
+
+        assert!(received.try_recv().is_err());
+        let deliveries = repository.deliveries(1, webhook.id).await.unwrap();
+        assert_eq!(None, deliveries[0].status_code);
+        assert_eq!(
+            Some("the address [127.0.0.1] is not allowed"),
+            deliveries[0].error.as_deref()
+        );
+    }
+
+    #[tokio::test]
+    async fn refuse_to_follow_redirect() {
+        let (addr, mut received) = spawn_receiver(0);
+        let repository = Arc::new(HashMapRepository::new());
+        let webhook = repository
+            .create(
+                1,
+                CreateWebhook::new(
+                    format!("http://{}/redirect", addr),
+                    SECRET.to_string(),
+                    vec![],
+                ),
+            )
+            .await
+            .expect("failed to create webhook");
+        let dispatcher = dispatcher(&repository);
+        assert!(
+            !dispatcher
+                .attempt(&webhook, TodoEventKind::Created, "{}", 1)
+                .await
+        );
+
+        assert!(received.try_recv().is_err());
+        let deliveries = repository.deliveries(1, webhook.id).await.unwrap();
+        assert_eq!(Some(307), deliveries[0].status_code);
+    }
+
+    #[tokio::test]
+    async fn dispatch_skips_filtered_webhooks() {
+        let (addr, mut received) = spawn_receiver(0);
+        let repository = Arc::new(HashMapRepository::new());
+        repository
+            .create(
+                1,
+                CreateWebhook::new(
+                    format!("http://{}/hook", addr),
+                    SECRET.to_string(),
+                    vec![TodoEventKind::Deleted],
+                ),
+            )
+            .await
+            .expect("failed to create webhook");
+        let dispatcher = dispatcher(&repository);
+        EventSink::deliver(&dispatcher, &created_event())
+            .await
+            .unwrap();
+        let deleted = TodoEvent {
+            kind: TodoEventKind::Deleted,
+            ..created_event()
+        };
+        EventSink::deliver(&dispatcher, &deleted).await.unwrap();
+        let (_, body) = received.recv().await.unwrap();
+        assert_eq!(serde_json::to_string(&deleted).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn stop_retrying_on_shutdown() {
+        let (addr, mut received) = spawn_receiver(usize::MAX);
+        let repository = Arc::new(HashMapRepository::new());
+        let webhook = repository
+            .create(
+                1,
+                CreateWebhook::new(format!("http://{}/hook", addr), SECRET.to_string(), vec![]),
+            )
+            .await
+            .expect("failed to create webhook");
+        let shutdown = CancellationToken::new();
+        let dispatcher = WebhookDispatcher::new(
+            Arc::clone(&repository),
+            RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_secs(60),
+                max_backoff: Duration::from_secs(60),
+            },
+            &loopback(),
+            TaskTracker::new(),
+            shutdown.clone(),
+        )
+        .unwrap();
+        let delivery = tokio::spawn(dispatcher.deliver(webhook.clone(), created_event()));
+        received.recv().await.unwrap();
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), delivery)
+            .await
+            .expect("delivery kept retrying after shutdown")
+            .unwrap();
+        assert_eq!(1, repository.deliveries(1, webhook.id).await.unwrap().len());
+    }
+}
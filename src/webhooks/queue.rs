@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use futures_util::future;
+use sqlx::{PgConnection, PgPool};
+use tokio_util::sync::CancellationToken;
+
+use crate::events::TodoEvent;
+use crate::outbox::OutboxSink;
+use crate::repositories::webhooks::{Webhook, WebhookRepository};
+use crate::webhooks::WebhookDispatcher;
+
+const BATCH_SIZE: i64 = 50;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a claimed delivery stays hidden from other workers; once it passes, the delivery
+/// is taken to have been lost with its worker and is attempted again.
+const LEASE: Duration = Duration::from_secs(60);
+
+/// Queues a delivery for every webhook subscribed to an outbox event, in the relay's
+/// transaction, for `QueueWorker` to make.
+pub(crate) struct WebhookQueue;
+
+#[async_trait]
+impl OutboxSink for WebhookQueue {
+    async fn deliver(&self, conn: &mut PgConnection, event: &TodoEvent) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_queue (webhook_id, payload)
+            SELECT id, $2 FROM webhooks
+            WHERE cardinality(events) = 0 OR $1 = ANY(events)
+            "#,
+            event.kind.as_str(),
+            serde_json::to_string(event)?,
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Makes the deliveries of the webhook queue, retrying failed ones after the dispatcher's
+/// backoff until they succeed or run out of attempts.
+///
+/// A delivery is claimed for `LEASE` before it is attempted and removed once it is settled, so
+/// it survives a crash and is made at least once. Several instances may work the queue.
+pub(crate) struct QueueWorker<W> {
+    pool: PgPool,
+    dispatcher: WebhookDispatcher<W>,
+}
+
+struct Claimed {
+    id: i64,
+    payload: String,
+    attempts: i32,
+    webhook: Webhook,
+}
+
+impl<W: WebhookRepository> QueueWorker<W> {
+    pub(crate) fn new(pool: PgPool, dispatcher: WebhookDispatcher<W>) -> Self {
+        Self { pool, dispatcher }
+    }
+
+    /// Polls the queue until `shutdown` is cancelled, finishing the deliveries in flight.
+    pub(crate) async fn run(self, shutdown: CancellationToken) {
+        while !shutdown.is_cancelled() {
+            match self.work().await {
+                Ok(count) if count as i64 == BATCH_SIZE => continue,
+                Ok(_) => {}
+                Err(e) => tracing::error!("failed to deliver queued webhooks: {}", e),
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Attempts one batch of due deliveries and returns how many were claimed.
+    async fn work(&self) -> anyhow::Result<usize> {
+        let claimed = self.claim().await?;
+        let count = claimed.len();
+        for result in
+            future::join_all(claimed.into_iter().map(|claimed| self.attempt(claimed))).await
+        {
+            if let Err(e) = result {
+                tracing::error!("failed to settle a queued webhook delivery: {}", e);
+            }
+        }
+        Ok(count)
+    }
+
+    async fn claim(&self) -> anyhow::Result<Vec<Claimed>> {
+        let rows = sqlx::query!(
+            r#"
+            WITH claimed AS (
+                UPDATE webhook_queue
+                SET attempts = attempts + 1,
+                    next_attempt_at = now() + make_interval(secs => $1)
+                WHERE id IN (
+                    SELECT id FROM webhook_queue
+                    WHERE next_attempt_at <= now()
+                    ORDER BY next_attempt_at
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, webhook_id, payload, attempts
+            )
+            SELECT claimed.id, claimed.payload, claimed.attempts,
+                   webhooks.id AS webhook_id, webhooks.user_id, webhooks.url, webhooks.secret,
+                   webhooks.events
+            FROM claimed JOIN webhooks ON webhooks.id = claimed.webhook_id
+            ORDER BY claimed.id
+            "#,
+            LEASE.as_secs_f64(),
+            BATCH_SIZE,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Claimed {
+                id: row.id,
+                payload: row.payload,
+                attempts: row.attempts,
+                webhook: Webhook {
+                    id: row.webhook_id,
+                    user_id: row.user_id,
+                    url: row.url,
+                    secret: row.secret,
+                    events: row.events,
+                },
+            })
+            .collect())
+    }
+
+    async fn attempt(&self, claimed: Claimed) -> anyhow::Result<()> {
+        let kind = match serde_json::from_str::<TodoEvent>(&claimed.payload) {
+            Ok(event) => event.kind,
+            Err(e) => {
+                tracing::error!("dropped unreadable webhook delivery {}: {}", claimed.id, e);
+                return self.remove(claimed.id).await;
+            }
+        };
+        let attempt = claimed.attempts as u32;
+        let succeeded = self
+            .dispatcher
+            .attempt(&claimed.webhook, kind, &claimed.payload, attempt)
+            .await;
+        if succeeded {
+            return self.remove(claimed.id).await;
+        }
+        let policy = &self.dispatcher.policy;
+        if attempt >= policy.max_attempts {
+            tracing::warn!(
+                "gave up delivering {:?} to webhook {} after {} attempts",
+                kind,
+                claimed.webhook.id,
+                attempt
+            );
+            return self.remove(claimed.id).await;
+        }
+        sqlx::query!(
+            r#"
+            UPDATE webhook_queue SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE id = $1
+            "#,
+            claimed.id,
+            policy.backoff(attempt).as_secs_f64(),
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, id: i64) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM webhook_queue WHERE id = $1
+            "#,
+            id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio_util::task::TaskTracker;
+
+    use crate::events::TodoEventKind;
+    use crate::repositories::postgres::{test_utils, PostgresRepository};
+    use crate::repositories::users::{CreateUser, UserRepository};
+    use crate::repositories::webhooks::CreateWebhook;
+    use crate::webhooks::tests::{created_event, loopback, policy, spawn_receiver, SECRET};
+
+    use super::*;
+
+    async fn create_webhook(
+        repository: &PostgresRepository,
+        url: String,
+        events: Vec<TodoEventKind>,
+    ) -> Webhook {
+        let user = UserRepository::create(
+            repository,
+            CreateUser {
+                username: "user".to_string(),
+                email: format!("user{}@example.com", events.len()),
+                password_hash: "hash".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+        WebhookRepository::create(
+            repository,
+            user.id,
+            CreateWebhook::new(url, SECRET.to_string(), events),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn queued(pool: &PgPool) -> Vec<(i32, i32)> {
+        sqlx::query_as("SELECT webhook_id, attempts FROM webhook_queue ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+    async fn queue_for_subscribed_webhooks() {
+        let pool = test_utils::database().await;
+        let repository = PostgresRepository::new(pool.clone());
+        let webhook = create_webhook(&repository, "http://localhost/".to_string(), vec![]).await;
+        create_webhook(
+            &repository,
+            "http://localhost/".to_string(),
+            vec![TodoEventKind::Deleted],
+        )
+        .await;
+
+        let mut conn = pool.acquire().await.unwrap();
+        WebhookQueue
+            .deliver(&mut conn, &created_event())
+            .await
+            .unwrap();
+        drop(conn);
+        assert_eq!(vec![(webhook.id, 0)], queued(&pool).await);
+
+        test_utils::drop_database(pool).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+    async fn retry_queued_delivery_until_it_succeeds() {
+        let (addr, mut received) = spawn_receiver(1);
+        let pool = test_utils::database().await;
+        let repository = Arc::new(PostgresRepository::new(pool.clone()));
+        let webhook = create_webhook(&repository, format!("http://{}/hook", addr), vec![]).await;
+        let mut conn = pool.acquire().await.unwrap();
+        WebhookQueue
+            .deliver(&mut conn, &created_event())
+            .await
+            .unwrap();
+        drop(conn);
+        let dispatcher = WebhookDispatcher::new(
+            Arc::clone(&repository),
+            policy(),
+            &loopback(),
+            TaskTracker::new(),
+            CancellationToken::new(),
+        )
+        .unwrap();
+        let worker = QueueWorker::new(pool.clone(), dispatcher);
+
+        assert_eq!(1, worker.work().await.unwrap());
+        received.recv().await.unwrap();
+        assert_eq!(vec![(webhook.id, 1)], queued(&pool).await);
+        tokio::time::sleep(policy().initial_backoff * 2).await;
+        assert_eq!(1, worker.work().await.unwrap());
+        received.recv().await.unwrap();
+        assert!(queued(&pool).await.is_empty());
+        let deliveries = repository
+            .deliveries(webhook.user_id, webhook.id)
+            .await
+            .unwrap();
+        let attempts: Vec<(i32, bool)> = deliveries
+            .iter()
+            .map(|delivery| (delivery.attempt, delivery.succeeded))
+            .collect();
+        assert_eq!(vec![(2, true), (1, false)], attempts);
+
+        test_utils::drop_database(pool).await;
+    }
+}
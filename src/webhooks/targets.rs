@@ -0,0 +1,130 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+use crate::config::WebhooksConfig;
+
+/// The addresses webhooks may be delivered to, so that users cannot have the server probe
+/// its own network; an allowed network is reached even when it is also denied.
+#[derive(Debug, Clone)]
+pub(crate) struct Targets {
+    allowed: Arc<[IpNet]>,
+    denied: Arc<[IpNet]>,
+}
+
+impl Targets {
+    pub(crate) fn new(config: &WebhooksConfig) -> Self {
+        let networks = |networks: &[String]| {
+            networks
+                .iter()
+                .map(|network| IpNet::from_str(network).expect("networks are validated"))
+                .collect()
+        };
+        Self {
+            allowed: networks(&config.allowed_networks),
+            denied: networks(&config.denied_networks),
+        }
+    }
+
+    pub(crate) fn allows(&self, ip: IpAddr) -> bool {
+        // e.g. ::ffff:127.0.0.1 reaches 127.0.0.1
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        self.allowed.iter().any(|network| network.contains(&ip))
+            || !self.denied.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Why `url` may not be delivered to, as far as is known before its host is resolved:
+    /// a scheme other than http and https, or an address that is not allowed.
+    pub(crate) fn check(&self, url: &str) -> Result<(), String> {
+        let url = url::Url::parse(url).map_err(|e| e.to_string())?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("the scheme [{}] is not allowed", url.scheme()));
+        }
+        // hosts given as an address are not resolved, so `Resolver` does not see them
+        let ip = match url.host() {
+            Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+            Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+            _ => return Ok(()),
+        };
+        if self.allows(ip) {
+            Ok(())
+        } else {
+            Err(format!("the address [{}] is not allowed", ip))
+        }
+    }
+}
+
+/// Resolves the hosts of webhooks to the addresses `Targets` allows, when they connect, so
+/// that a host cannot be pointed elsewhere after it was checked.
+pub(crate) struct Resolver {
+    pub(crate) targets: Targets,
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let targets = self.targets.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| targets.allows(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("[{}] resolves to no address that is allowed", host).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deny_internal_addresses_by_default() {
+        let targets = Targets::new(&WebhooksConfig::default());
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://10.1.2.3/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.0.1]/hook",
+            "http://[fe80::1]/hook",
+            "ftp://example.com/hook",
+            "file:///etc/passwd",
+        ] {
+            assert!(targets.check(url).is_err(), "{} was allowed", url);
+        }
+        assert!(targets.check("https://93.184.216.34/hook").is_ok());
+        assert!(targets.check("https://example.com/hook").is_ok());
+    }
+
+    #[test]
+    fn allow_network_despite_denial() {
+        let config = WebhooksConfig {
+            allowed_networks: vec!["10.1.2.0/24".to_string()],
+            ..WebhooksConfig::default()
+        };
+        let targets = Targets::new(&config);
+        assert!(targets.check("http://10.1.2.3/hook").is_ok());
+        assert!(targets.check("http://10.1.3.3/hook").is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_to_allowed_addresses_only() {
+        let resolver = Resolver {
+            targets: Targets::new(&WebhooksConfig::default()),
+        };
+        let result = resolver.resolve(Name::from_str("localhost").unwrap()).await;
+        assert!(result.is_err());
+    }
+}